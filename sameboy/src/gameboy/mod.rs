@@ -27,6 +27,8 @@ pub use cartridge::camera::CameraProvider;
 pub use cartridge::rumble::{RumbleMode, RumbleReceiver};
 pub use link::printer::PrinterReceiver;
 pub use link::workboy_key::WorkboyKey;
+pub use direct_access::{DirectAccess, DirectAccessType};
+pub use video::{ColorCorrectionMode, DmgPalette};
 use crate::gameboy::video::SCREEN_BUF_SIZE;
 
 pub struct Gameboy {
@@ -148,6 +150,22 @@ impl Gameboy {
         }
     }
 
+    /// Run for a single frame with any number of Gameboys in lockstep, always
+    /// advancing the one that is furthest behind.
+    pub fn run_frames_sync(consoles: &mut [Gameboy]) {
+        unsafe {
+            for gb in consoles.iter_mut() {
+                (*gb.inner_mut()).vblank_occurred.store(false, Ordering::Release);
+            }
+
+            let mut ticks = vec![0u64; consoles.len()];
+            while consoles.iter().any(|gb| !(*gb.inner()).vblank_occurred.load(Ordering::Acquire)) {
+                let (i, _) = ticks.iter().enumerate().min_by_key(|(_, t)| **t).unwrap();
+                ticks[i] += consoles[i].run() as u64;
+            }
+        }
+    }
+
     /// Get the usual frame rate.
     pub fn usual_frame_rate(&mut self) -> f64 {
         unsafe {
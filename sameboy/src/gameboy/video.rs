@@ -1,7 +1,7 @@
 use std::{ptr, slice};
 use std::mem;
 use std::sync::atomic::Ordering;
-use sameboy_sys::{GB_color_correction_mode_t, GB_color_correction_mode_t_GB_COLOR_CORRECTION_CORRECT_CURVES, GB_color_correction_mode_t_GB_COLOR_CORRECTION_DISABLED, GB_color_correction_mode_t_GB_COLOR_CORRECTION_LOW_CONTRAST, GB_color_correction_mode_t_GB_COLOR_CORRECTION_MODERN_ACCURATE, GB_color_correction_mode_t_GB_COLOR_CORRECTION_MODERN_BALANCED, GB_color_correction_mode_t_GB_COLOR_CORRECTION_MODERN_BOOST_CONTRAST, GB_color_correction_mode_t_GB_COLOR_CORRECTION_REDUCE_CONTRAST, GB_convert_rgb15, GB_draw_tilemap, GB_draw_tileset, GB_gameboy_t, GB_get_oam_info, GB_get_pixels_output, GB_get_screen_height, GB_get_screen_width, GB_is_background_rendering_disabled, GB_is_object_rendering_disabled, GB_is_odd_frame, GB_map_type_t, GB_map_type_t_GB_MAP_9800, GB_map_type_t_GB_MAP_9C00, GB_map_type_t_GB_MAP_AUTO, GB_oam_info_t, GB_palette_type_t, GB_palette_type_t_GB_PALETTE_AUTO, GB_palette_type_t_GB_PALETTE_BACKGROUND, GB_palette_type_t_GB_PALETTE_NONE, GB_palette_type_t_GB_PALETTE_OAM, GB_set_background_rendering_disabled, GB_set_color_correction_mode, GB_set_light_temperature, GB_set_object_rendering_disabled, GB_set_pixels_output, GB_set_rendering_disabled, GB_set_rgb_encode_callback, GB_set_vblank_callback, GB_tileset_type_t, GB_tileset_type_t_GB_TILESET_8000, GB_tileset_type_t_GB_TILESET_8800, GB_tileset_type_t_GB_TILESET_AUTO, GB_vblank_type_t};
+use sameboy_sys::{GB_color_correction_mode_t, GB_color_correction_mode_t_GB_COLOR_CORRECTION_CORRECT_CURVES, GB_color_correction_mode_t_GB_COLOR_CORRECTION_DISABLED, GB_color_correction_mode_t_GB_COLOR_CORRECTION_LOW_CONTRAST, GB_color_correction_mode_t_GB_COLOR_CORRECTION_MODERN_ACCURATE, GB_color_correction_mode_t_GB_COLOR_CORRECTION_MODERN_BALANCED, GB_color_correction_mode_t_GB_COLOR_CORRECTION_MODERN_BOOST_CONTRAST, GB_color_correction_mode_t_GB_COLOR_CORRECTION_REDUCE_CONTRAST, GB_convert_rgb15, GB_draw_tilemap, GB_draw_tileset, GB_gameboy_t, GB_get_oam_info, GB_get_pixels_output, GB_get_screen_height, GB_get_screen_width, GB_is_background_rendering_disabled, GB_is_object_rendering_disabled, GB_is_odd_frame, GB_map_type_t, GB_map_type_t_GB_MAP_9800, GB_map_type_t_GB_MAP_9C00, GB_map_type_t_GB_MAP_AUTO, GB_oam_info_t, GB_palette_t, GB_PALETTE_DMG, GB_PALETTE_GBL, GB_PALETTE_GREY, GB_PALETTE_MGB, GB_palette_type_t, GB_palette_type_t_GB_PALETTE_AUTO, GB_palette_type_t_GB_PALETTE_BACKGROUND, GB_palette_type_t_GB_PALETTE_NONE, GB_palette_type_t_GB_PALETTE_OAM, GB_set_background_rendering_disabled, GB_set_color_correction_mode, GB_set_light_temperature, GB_set_object_rendering_disabled, GB_set_palette, GB_set_pixels_output, GB_set_rendering_disabled, GB_set_rgb_encode_callback, GB_set_vblank_callback, GB_tileset_type_t, GB_tileset_type_t_GB_TILESET_8000, GB_tileset_type_t_GB_TILESET_8800, GB_tileset_type_t_GB_TILESET_AUTO, GB_vblank_type_t};
 use super::Gameboy;
 
 #[cfg(feature = "image")]
@@ -136,6 +136,28 @@ impl From<ColorCorrectionMode> for GB_color_correction_mode_t {
     }
 }
 
+/// Built-in palettes for monochrome models
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DmgPalette {
+    Grey,
+    Dmg,
+    Mgb,
+    Gbl
+}
+
+impl DmgPalette {
+    fn as_palette(&self) -> &'static GB_palette_t {
+        unsafe {
+            match self {
+                DmgPalette::Grey => &GB_PALETTE_GREY,
+                DmgPalette::Dmg => &GB_PALETTE_DMG,
+                DmgPalette::Mgb => &GB_PALETTE_MGB,
+                DmgPalette::Gbl => &GB_PALETTE_GBL
+            }
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 #[repr(transparent)]
 pub struct OamInfo(GB_oam_info_t);
@@ -350,6 +372,13 @@ impl Gameboy {
         }
     }
 
+    /// Set the palette used by monochrome models.
+    pub fn set_palette(&mut self, palette: DmgPalette) {
+        unsafe {
+            GB_set_palette(self.as_mut_ptr(), palette.as_palette());
+        }
+    }
+
     pub fn set_light_temperature(&mut self, temperature: f64) {
         unsafe {
             GB_set_light_temperature(self.as_mut_ptr(), temperature);
@@ -4,10 +4,20 @@ use std::path::PathBuf;
 use indicatif::{FormattedDuration, HumanBytes, ProgressBar, ProgressStyle};
 use std::fmt::Write;
 use std::fs;
-use sameboy::{Model, Revision};
+use std::str::FromStr;
+use std::time::Duration;
+use anyhow::{Result, ensure};
+use sameboy::{ColorCorrectionMode, DmgPalette, HighpassFilterMode, Model, Revision, VideoStandard};
 use tiny_skia::Color;
 use crate::config::Config;
-use crate::renderer::{Renderer, render_options::{RendererOptions, RenderInput, StopCondition}, vgm};
+use crate::video_builder::video_options::is_audio_only_path;
+use crate::renderer::{Renderer, render_options::{FadeCurve, RendererOptions, RenderInput, StartPosition, StopCondition}, album::{self, AlbumTrack, Chapter}, metadata, vgm, uge, gbt};
+
+enum RenderMode {
+    Single,
+    Batch(String),
+    Album(u64)
+}
 
 fn model_value_parser(s: &str) -> Result<Model, String> {
     match s.replace("-", "").to_lowercase().as_str() {
@@ -20,10 +30,45 @@ fn model_value_parser(s: &str) -> Result<Model, String> {
         "cgb" | "cgbe" => Ok(Model::CGB(Revision::RevE)),
         "mgb" => Ok(Model::MGB),
         "agb" => Ok(Model::AGB),
+        "sgb" | "sgbntsc" => Ok(Model::SGB(VideoStandard::NTSC, true)),
+        "sgbpal" => Ok(Model::SGB(VideoStandard::PAL, true)),
+        "sgb2" => Ok(Model::SGB2(true)),
         _ => Err("Invalid model string".to_string())
     }
 }
 
+fn color_correction_value_parser(s: &str) -> Result<ColorCorrectionMode, String> {
+    match s.to_lowercase().as_str() {
+        "disabled" => Ok(ColorCorrectionMode::Disabled),
+        "correct-curves" => Ok(ColorCorrectionMode::CorrectCurves),
+        "balanced" => Ok(ColorCorrectionMode::Balanced),
+        "boost-contrast" => Ok(ColorCorrectionMode::BoostContrast),
+        "reduce-contrast" => Ok(ColorCorrectionMode::ReduceContrast),
+        "low-contrast" => Ok(ColorCorrectionMode::LowContrast),
+        "accurate" => Ok(ColorCorrectionMode::Accurate),
+        _ => Err("Invalid color correction mode string".to_string())
+    }
+}
+
+fn dmg_palette_value_parser(s: &str) -> Result<DmgPalette, String> {
+    match s.to_lowercase().as_str() {
+        "grey" | "gray" => Ok(DmgPalette::Grey),
+        "dmg" => Ok(DmgPalette::Dmg),
+        "mgb" => Ok(DmgPalette::Mgb),
+        "gbl" => Ok(DmgPalette::Gbl),
+        _ => Err("Invalid DMG palette string".to_string())
+    }
+}
+
+fn highpass_mode_value_parser(s: &str) -> Result<HighpassFilterMode, String> {
+    match s.to_lowercase().as_str() {
+        "off" => Ok(HighpassFilterMode::Off),
+        "accurate" => Ok(HighpassFilterMode::Accurate),
+        "remove-dc" => Ok(HighpassFilterMode::RemoveDCOffset),
+        _ => Err("Invalid high-pass filter mode string".to_string())
+    }
+}
+
 fn color_value_parser(s: &str) -> Result<Color, String> {
     let parsed_color = s.parse::<CssColor>()
         .map_err(|e| e.to_string())?;
@@ -44,7 +89,48 @@ fn codec_option_value_parser(s: &str) -> Result<(String, String), String> {
     Ok((key.to_string(), value.to_string()))
 }
 
-fn get_renderer_options() -> RendererOptions {
+fn metadata_value_parser(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=')
+        .ok_or("Invalid metadata specification (must be of the form 'key=value').".to_string())?;
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+fn format_filename(template: &str, track: &AlbumTrack) -> Result<String, String> {
+    let mut result = String::with_capacity(template.len() + track.title.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let end = start + rest[start..].find('}').ok_or("Unclosed '{' in filename template".to_string())?;
+        let (name, spec) = rest[(start + 1)..end].split_once(':').unwrap_or((&rest[(start + 1)..end], ""));
+
+        let value = match name {
+            "index" => (track.index as u32 + 1).to_string(),
+            "title" => track.title
+                .chars()
+                .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+                .collect::<String>()
+                .trim_end_matches(['.', ' '])
+                .to_string(),
+            _ => return Err(format!("Unknown filename template field '{}'", name))
+        };
+        let width = match spec {
+            "" => 0,
+            spec => usize::from_str(spec).map_err(|_| format!("Invalid width '{}' in filename template", spec))?
+        };
+        let fill = if spec.starts_with('0') { '0' } else { ' ' };
+
+        result.extend(std::iter::repeat(fill).take(width.saturating_sub(value.chars().count())));
+        result.push_str(&value);
+        rest = &rest[(end + 1)..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+fn get_renderer_options() -> (RendererOptions, RenderMode) {
     let matches = Command::new("GBPresenter")
         .arg(arg!(-c --"video-codec" <CODEC> "Set the output video codec")
             .required(false)
@@ -62,7 +148,7 @@ fn get_renderer_options() -> RendererOptions {
             .required(false)
             .value_parser(value_parser!(i32))
             .default_value("44100"))
-        .arg(arg!(-T --"track" <TRACK> "Select the 1-indexed track to play")
+        .arg(arg!(-T --"track" <TRACK> "Select the 1-indexed track to play, repeat it for each extra console")
             .required(false)
             .value_parser(value_parser!(u8))
             .default_value("1")
@@ -75,6 +161,24 @@ fn get_renderer_options() -> RendererOptions {
             .required(false)
             .value_parser(value_parser!(u64))
             .default_value("180"))
+        .arg(arg!(--"start-at" <POSITION> "Fast-forward to a position before starting the output")
+            .required(false)
+            .value_parser(value_parser!(StartPosition)))
+        .arg(arg!(--"fadein" <FRAMES> "Set the audio fade-in length in frames")
+            .required(false)
+            .value_parser(value_parser!(u64))
+            .default_value("0"))
+        .arg(arg!(--"fade-curve" <CURVE> "Set the fade-in/fadeout curve (linear, exponential, equal-power, s-curve)")
+            .required(false)
+            .value_parser(value_parser!(FadeCurve))
+            .default_value("linear"))
+        .arg(arg!(--"fade-video" "Fade the video to and from black along with the audio")
+            .required(false)
+            .conflicts_with("audio-only"))
+        .arg(arg!(--"normalize" <LUFS> "Normalize the audio to an integrated loudness (e.g. -14), measured in a separate pass")
+            .required(false)
+            .allow_negative_numbers(true)
+            .value_parser(value_parser!(f64)))
         .arg(arg!(--"ow" <WIDTH> "Set the output video width")
             .required(false)
             .value_parser(value_parser!(u32))
@@ -91,10 +195,35 @@ fn get_renderer_options() -> RendererOptions {
             .required(false)
             .value_parser(codec_option_value_parser)
             .action(ArgAction::Append))
+        .arg(arg!(--"meta" <METADATA> "Set a container metadata field, overriding the one read from the input (key=value)")
+            .required(false)
+            .value_parser(metadata_value_parser)
+            .action(ArgAction::Append))
         .arg(arg!(-m --"model" <MODEL> "GameBoy model to emulate")
             .required(false)
             .value_parser(model_value_parser)
             .default_value("CGB-E"))
+        .arg(arg!(--"highpass" <MODE> "High-pass filter of the emulated audio output (off, accurate, remove-dc)")
+            .required(false)
+            .value_parser(highpass_mode_value_parser))
+        .arg(arg!(--"interference" <VOLUME> "Volume of the console's electrical interference in the audio, from 0.0 to 1.0")
+            .required(false)
+            .value_parser(value_parser!(f64)))
+        .arg(arg!(--"prosound" "Sound like a ProSound-modded console, without interference or DC offset")
+            .required(false)
+            .conflicts_with_all(["highpass", "interference"]))
+        .arg(arg!(--"screen" "Show the Game Boy's screen next to the piano roll")
+            .required(false)
+            .conflicts_with("audio-only"))
+        .arg(arg!(--"color-correction" <MODE> "Color correction for the shown screen")
+            .required(false)
+            .value_parser(color_correction_value_parser))
+        .arg(arg!(--"dmg-palette" <PALETTE> "Palette for the shown screen of monochrome models (grey, dmg, mgb, gbl)")
+            .required(false)
+            .value_parser(dmg_palette_value_parser))
+        .arg(arg!(--"hide-sgb-border" "Only show the Game Boy's screen without the border on SGB models")
+            .required(false)
+            .requires("screen"))
         .arg(arg!(-k --"channel-color" "Set the colors for a channel.")
             .required(false)
             .num_args(3..=18)
@@ -127,10 +256,57 @@ fn get_renderer_options() -> RendererOptions {
             .num_args(4)
             .value_names(["ROM", "SAV", "ROM2X", "SAV2X"])
             .value_parser(value_parser!(PathBuf)))
+        .arg(Arg::new("multilsdj")
+            .long("multilsdj")
+            .help("Any number of unlinked LSDj ROM/SAV pairs to render side by side")
+            .required(false)
+            .num_args(2..)
+            .value_names(["ROM", "SAV"])
+            .value_parser(value_parser!(PathBuf)))
+        .arg(Arg::new("lsdsng")
+            .long("lsdsng")
+            .help("LSDj ROM and one or more .lsdsng/.lsdprj songs to render")
+            .required(false)
+            .num_args(2..)
+            .value_names(["ROM", "SONGS"])
+            .value_parser(value_parser!(PathBuf)))
         .arg(arg!(-v --"vgm" <VGM> "VGM file to render")
             .required(false)
             .value_parser(value_parser!(PathBuf)))
-        .arg(arg!(<output> "Output video file")
+        .arg(arg!(--"vgm-direct" "Play the VGM by writing its registers directly instead of through Pegmode's driver")
+            .required(false)
+            .requires("vgm"))
+        .arg(arg!(-u --"uge" <UGE> "hUGETracker module to render")
+            .required(false)
+            .value_parser(value_parser!(PathBuf)))
+        .arg(arg!(--"gbt" <MOD> "GBT Player compatible MOD file to render")
+            .required(false)
+            .value_parser(value_parser!(PathBuf)))
+        .arg(arg!(-b --"batch" "Render every track of the GBS/LSDj input into separate files in the output directory")
+            .required(false))
+        .arg(arg!(--"name-template" <TEMPLATE> "Output filename template for --batch ({index}, {title})")
+            .required(false)
+            .default_value("{index:02} - {title}.mp4"))
+        .arg(arg!(--"audio-only" "Skip the visualization and only write audio (implied by .wav/.flac/.opus outputs)")
+            .required(false))
+        .arg(arg!(--"stems" <FORMAT> "Also export each APU channel as a separate audio file next to the output")
+            .required(false)
+            .value_parser(["wav", "flac"]))
+        .arg(arg!(--"stem-streams" "Add each APU channel to the video as an extra audio stream after the mix")
+            .required(false)
+            .conflicts_with("audio-only"))
+        .arg(arg!(--"midi" "Also write the APU activity to a MIDI file next to the output")
+            .required(false))
+        .arg(arg!(--"record-vgm" "Also record the APU register writes to a VGM file next to the output")
+            .required(false))
+        .arg(arg!(-a --"album" "Render every track of the GBS/LSDj input into one video with chapters")
+            .required(false)
+            .conflicts_with("batch"))
+        .arg(arg!(--"album-gap" <FRAMES> "Set the silent gap between tracks for --album in frames")
+            .required(false)
+            .value_parser(value_parser!(u64))
+            .default_value("120"))
+        .arg(arg!(<output> "Output video file (or directory, with --batch)")
             .value_parser(value_parser!(PathBuf))
             .required(true))
         .get_matches();
@@ -149,25 +325,56 @@ fn get_renderer_options() -> RendererOptions {
         let rom_path_2x = lsdj_files.next().cloned().expect("ROM2X file argument required for --2xlsdj").to_str().unwrap().to_string();
         let sav_path_2x = lsdj_files.next().cloned().expect("SAV2X file argument required for --2xlsdj").to_str().unwrap().to_string();
         options.input = RenderInput::LSDj2x(rom_path, sav_path, rom_path_2x, sav_path_2x);
+    } else if let Some(lsdj_files) = matches.get_many::<PathBuf>("multilsdj") {
+        let lsdj_files: Vec<String> = lsdj_files.map(|p| p.to_str().unwrap().to_string()).collect();
+        if lsdj_files.len() % 2 != 0 {
+            panic!("--multilsdj takes ROM/SAV pairs, got an odd number of files");
+        }
+        let pairs = lsdj_files
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
+        options.input = RenderInput::LSDjMulti(pairs);
+    } else if let Some(mut lsdsng_files) = matches.get_many::<PathBuf>("lsdsng") {
+        let rom_path = lsdsng_files.next().cloned().expect("ROM file argument required for --lsdsng").to_str().unwrap().to_string();
+        let song_paths: Vec<String> = lsdsng_files.map(|p| p.to_str().unwrap().to_string()).collect();
+        options.input = RenderInput::LSDjSongs(rom_path, song_paths);
     } else if let Some(gbs_file) = matches.get_one::<PathBuf>("gbs") {
         options.input = RenderInput::GBS(gbs_file.to_str().unwrap().to_string());
     } else if let Some(vgm_file) = matches.get_one::<PathBuf>("vgm") {
         options.input = RenderInput::VGM(vgm_file.to_str().unwrap().to_string(), 60, 0);
+    } else if let Some(uge_file) = matches.get_one::<PathBuf>("uge") {
+        options.input = RenderInput::UGE(uge_file.to_str().unwrap().to_string());
+    } else if let Some(mod_file) = matches.get_one::<PathBuf>("gbt") {
+        options.input = RenderInput::GBT(mod_file.to_str().unwrap().to_string());
     } else {
-        panic!("One of --gbs/--lsdj/--2xlsdj/--vgm is required");
+        panic!("One of --gbs/--lsdj/--2xlsdj/--multilsdj/--lsdsng/--vgm/--uge/--gbt is required");
     }
 
+    let mode = if matches.get_flag("batch") {
+        RenderMode::Batch(matches.get_one::<String>("name-template").cloned().unwrap())
+    } else if matches.get_flag("album") {
+        RenderMode::Album(matches.get_one::<u64>("album-gap").cloned().unwrap())
+    } else {
+        RenderMode::Single
+    };
+
     options.video_options.output_path = matches.get_one::<PathBuf>("output").cloned().unwrap().to_str().unwrap().to_string();
     options.video_options.video_codec = matches.get_one::<String>("video-codec").cloned().unwrap();
     options.video_options.audio_codec = matches.get_one::<String>("audio-codec").cloned().unwrap();
     options.video_options.pixel_format_out = matches.get_one::<String>("pixel-format").cloned().unwrap();
     options.video_options.sample_format_out = matches.get_one::<String>("sample-format").cloned().unwrap();
 
-    if options.video_options.output_path.ends_with(".mov") {
+    let container_path = match &mode {
+        RenderMode::Batch(template) => template,
+        RenderMode::Single | RenderMode::Album(_) => &options.video_options.output_path
+    };
+    if container_path.ends_with(".mov") {
         // Fairly close approximation of the Game Boy's frame rate with a timebase denominator <100000.
         // Required to avoid "codec timebase is very high" warning from the QuickTime encoder.
         options.video_options.video_time_base = (1_097, 65_536).into();
     }
+    options.video_options.audio_only = matches.get_flag("audio-only") || is_audio_only_path(container_path);
 
     let sample_rate = matches.get_one::<i32>("sample-rate").cloned().unwrap();
     options.video_options.sample_rate = sample_rate;
@@ -177,20 +384,21 @@ fn get_renderer_options() -> RendererOptions {
         match i {
             0 => options.track_index = track.saturating_sub(1),
             1 => options.track_index_2x = track.saturating_sub(1),
-            _ => panic!("Too many arguments for --track")
+            _ => options.extra_track_indices.push(track.saturating_sub(1))
         };
     }
 
-    options.stop_condition = match (matches.get_one::<StopCondition>("stop-at").cloned().unwrap(), &options.input) {
-        (StopCondition::Loops(loops), RenderInput::VGM(vgm_path, engine_rate, _)) => {
-            let vgm_s = vgm::Vgm::open(vgm_path).unwrap();
-            let frames = vgm::duration_frames(&vgm_s, *engine_rate, loops);
-            StopCondition::Frames(frames as u64)
-        },
-        (stop_condition, _) => stop_condition
-    };
-
     options.fadeout_length = matches.get_one::<u64>("stop-fadeout").cloned().unwrap();
+    options.start_at = matches.get_one::<StartPosition>("start-at").cloned();
+    options.fadein_length = matches.get_one::<u64>("fadein").cloned().unwrap();
+    options.fade_curve = matches.get_one::<FadeCurve>("fade-curve").cloned().unwrap();
+    options.fade_video = matches.get_flag("fade-video");
+    options.loudness_target = matches.get_one::<f64>("normalize").cloned();
+    options.stem_format = matches.get_one::<String>("stems").cloned();
+    options.stem_streams = matches.get_flag("stem-streams");
+    options.midi_export = matches.get_flag("midi");
+    options.vgm_export = matches.get_flag("record-vgm");
+    options.vgm_direct = matches.get_flag("vgm-direct");
 
     let ow = matches.get_one::<u32>("ow").cloned().unwrap();
     let oh = matches.get_one::<u32>("oh").cloned().unwrap();
@@ -198,6 +406,28 @@ fn get_renderer_options() -> RendererOptions {
 
     options.model = matches.get_one::<Model>("model").cloned().unwrap();
 
+    options.stop_condition = match (matches.get_one::<StopCondition>("stop-at").cloned().unwrap(), &options.input) {
+        (StopCondition::Loops(loops), RenderInput::VGM(vgm_path, engine_rate, tma_offset)) => {
+            let vgm_s = vgm::Vgm::open(vgm_path).unwrap();
+            let frames = match options.vgm_direct {
+                true => vgm::direct_duration_frames(&vgm_s, options.frame_rate(), loops),
+                false => vgm::duration_frames(&vgm_s, *engine_rate, *tma_offset, loops)
+            };
+            StopCondition::Frames(frames as u64)
+        },
+        (StopCondition::Loops(loops), RenderInput::UGE(uge_path)) => {
+            let song = uge::Uge::open(uge_path).unwrap();
+            let frames = uge::duration_frames(&song, loops).unwrap();
+            StopCondition::Frames(frames as u64)
+        },
+        (StopCondition::Loops(loops), RenderInput::GBT(mod_path)) => {
+            let module = gbt::Module::open(mod_path).unwrap();
+            let frames = gbt::duration_frames(&module, loops).unwrap();
+            StopCondition::Frames(frames as u64)
+        },
+        (stop_condition, _) => stop_condition
+    };
+
     if let Some(video_options) = matches.get_many::<(String, String)>("video-option") {
         for (k, v) in video_options.cloned() {
             options.video_options.video_codec_params.insert(k, v);
@@ -208,6 +438,11 @@ fn get_renderer_options() -> RendererOptions {
             options.video_options.audio_codec_params.insert(k, v);
         }
     }
+    if let Some(metadata) = matches.get_many::<(String, String)>("meta") {
+        for (k, v) in metadata.cloned() {
+            options.video_options.metadata.insert(k, v);
+        }
+    }
 
     options.config = match matches.get_one::<PathBuf>("import-config") {
         Some(config_path) => {
@@ -217,6 +452,31 @@ fn get_renderer_options() -> RendererOptions {
         None => Config::default()
     };
 
+    if let Some(highpass_mode) = matches.get_one::<HighpassFilterMode>("highpass") {
+        options.config.audio.highpass_mode = *highpass_mode;
+        options.config.audio.prosound = false;
+    }
+    if let Some(interference) = matches.get_one::<f64>("interference") {
+        options.config.audio.interference = *interference;
+        options.config.audio.prosound = false;
+    }
+    if matches.get_flag("prosound") {
+        options.config.audio.prosound = true;
+    }
+
+    if matches.get_flag("screen") {
+        options.config.screen.enabled = true;
+    }
+    if let Some(color_correction) = matches.get_one::<ColorCorrectionMode>("color-correction") {
+        options.config.screen.color_correction = *color_correction;
+    }
+    if let Some(dmg_palette) = matches.get_one::<DmgPalette>("dmg-palette") {
+        options.config.screen.dmg_palette = *dmg_palette;
+    }
+    if matches.get_flag("hide-sgb-border") {
+        options.config.screen.sgb_border = false;
+    }
+
     if let Some(channel_settings) = matches.get_occurrences::<String>("channel-color") {
         for channel_setting_parts in channel_settings.map(Iterator::collect::<Vec<&String>>) {
             let chip = channel_setting_parts
@@ -256,13 +516,10 @@ fn get_renderer_options() -> RendererOptions {
         }
     }
 
-    options
+    (options, mode)
 }
 
-pub fn run() {
-    let options = get_renderer_options();
-    let mut renderer = Renderer::new(options).unwrap();
-
+fn render_track(renderer: &mut Renderer, finish_message: &str) -> Result<()> {
     let pb = ProgressBar::new(0);
     let pb_style_initial = ProgressStyle::with_template("{msg}\n{spinner} Waiting for loop detection...")
         .unwrap();
@@ -270,9 +527,8 @@ pub fn run() {
         .unwrap();
     pb.set_style(pb_style_initial);
 
-    renderer.start_encoding().unwrap();
     loop {
-        if !(renderer.step().unwrap()) {
+        if !(renderer.step()?) {
             break;
         }
 
@@ -317,6 +573,185 @@ pub fn run() {
         pb.set_message(message);
     }
 
-    pb.finish_with_message("Finalizing encode...");
-    renderer.finish_encoding().unwrap();
+    pb.finish_with_message(finish_message.to_string());
+
+    Ok(())
+}
+
+/// Run the render once without any output to measure its loudness, and set the gain that reaches the target
+fn normalize_loudness<F>(options: RendererOptions, render_fn: F) -> Result<RendererOptions>
+where
+    F: Fn(RendererOptions) -> Result<Renderer>
+{
+    if options.loudness_target.is_none() {
+        return Ok(options);
+    }
+
+    println!("Measuring loudness...");
+    let renderer = render_fn(options.loudness_pass())?;
+    Ok(apply_loudness(options, &renderer))
+}
+
+/// Set the gain that brings the loudness measured by a loudness pass to the target
+fn apply_loudness(options: RendererOptions, renderer: &Renderer) -> RendererOptions {
+    let target = match options.loudness_target {
+        Some(target) => target,
+        None => return options
+    };
+
+    let loudness = renderer.loudness().unwrap();
+    let gain_db = loudness.normalization_gain(target);
+    match loudness.integrated {
+        Some(integrated) => println!(
+            "Integrated loudness: {:.1} LUFS, true peak: {:.1} dBTP, applying {:+.1} dB",
+            integrated, loudness.true_peak, gain_db
+        ),
+        None => println!("The render is silent, skipping loudness normalization")
+    }
+
+    let mut options = options;
+    options.gain_db = gain_db;
+    options
+}
+
+fn render_pass(options: RendererOptions) -> Result<Renderer> {
+    let finish_message = match options.loudness_pass {
+        true => "Measured loudness",
+        false => "Finalizing encode..."
+    };
+    let mut renderer = Renderer::new(options)?;
+
+    renderer.start_encoding()?;
+    render_track(&mut renderer, finish_message)?;
+    renderer.finish_encoding()?;
+
+    Ok(renderer)
+}
+
+fn render(options: RendererOptions) -> Result<(Duration, usize)> {
+    let options = normalize_loudness(options, render_pass)?;
+    let renderer = render_pass(options)?;
+
+    Ok((renderer.encoded_duration(), renderer.encoded_size()))
+}
+
+fn render_album(options: RendererOptions, gap_frames: u64) -> Result<()> {
+    // Chapters have to be known before the header is written, so a pass without any output measures them
+    // first. Rendering is deterministic, and the same pass measures the loudness of the whole album, so the
+    // gain is the same for every track.
+    println!("Measuring tracks...");
+    let (renderer, chapters) = render_album_pass(options.loudness_pass(), gap_frames, &[])?;
+    let options = apply_loudness(options, &renderer);
+    render_album_pass(options, gap_frames, &chapters)?;
+
+    Ok(())
+}
+
+fn render_album_pass(options: RendererOptions, gap_frames: u64, chapters: &[Chapter]) -> Result<(Renderer, Vec<Chapter>)> {
+    let tracks = album::tracks(&options.input)?;
+    ensure!(!tracks.is_empty(), "No tracks to render!");
+
+    let mut options = options;
+    options.track_index = tracks[0].index;
+    options.album_mode = true;
+    let loudness_pass = options.loudness_pass;
+    let mut renderer = Renderer::new(options)?;
+    for chapter in chapters {
+        renderer.add_chapter(&chapter.title, chapter.start, chapter.end)?;
+    }
+
+    let mut chapter_starts: Vec<Duration> = Vec::with_capacity(tracks.len());
+    for (i, track) in tracks.iter().enumerate() {
+        if i == 0 {
+            renderer.start_encoding()?;
+        } else {
+            renderer.render_gap(gap_frames)?;
+            renderer.start_next_track(track.index)?;
+        }
+        chapter_starts.push(renderer.encoded_duration());
+
+        println!("Rendering track {}/{}: {}", i + 1, tracks.len(), track.title);
+        render_track(&mut renderer, &format!("Finished track {}/{}", i + 1, tracks.len()))?;
+    }
+
+    let album_end = renderer.encoded_duration();
+    let measured_chapters = tracks.iter()
+        .enumerate()
+        .map(|(i, track)| Chapter {
+            title: track.title.clone(),
+            start: chapter_starts[i],
+            end: chapter_starts.get(i + 1).cloned().unwrap_or(album_end)
+        })
+        .collect();
+
+    if !loudness_pass {
+        println!("Finalizing encode...");
+    }
+    renderer.finish_encoding()?;
+
+    Ok((renderer, measured_chapters))
+}
+
+fn render_batch(options: RendererOptions, template: &str) {
+    let tracks = album::tracks(&options.input).unwrap();
+    let output_dir = PathBuf::from(&options.video_options.output_path);
+    fs::create_dir_all(&output_dir).expect("Failed to create output directory!");
+
+    let output_paths: Vec<String> = tracks.iter()
+        .map(|track| format_filename(template, track).map(|f| output_dir.join(f).to_str().unwrap().to_string()))
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    let mut results: Vec<Result<(Duration, usize)>> = Vec::with_capacity(tracks.len());
+    for (track, output_path) in tracks.iter().zip(&output_paths) {
+        println!("Rendering track {}/{}: {}", track.index as usize + 1, tracks.len(), track.title);
+
+        let mut track_options = options.clone();
+        track_options.track_index = track.index;
+        track_options.video_options.output_path = output_path.clone();
+        // The renderer fills in the input's own title, so only tracks without one are titled "Track N"
+        let has_title = metadata::from_input(&track_options)
+            .map(|fields| fields.iter().any(|(key, _)| *key == "title"))
+            .unwrap_or(false);
+        if !has_title {
+            track_options.video_options.metadata
+                .entry("title".to_string())
+                .or_insert(format!("Track {}", track.index as u32 + 1));
+        }
+
+        let result = render(track_options);
+        if let Err(e) = &result {
+            println!("Failed to render track {}: {}", track.index as usize + 1, e);
+        }
+        results.push(result);
+    }
+
+    let title_width = tracks.iter().map(|t| t.title.chars().count()).max().unwrap_or(0).max(5);
+    println!();
+    println!("{:>3}  {:<title_width$}  {:>10}  {:>10}  {}", "#", "Title", "Duration", "Size", "Output");
+    for ((track, output_path), result) in tracks.iter().zip(&output_paths).zip(&results) {
+        let (duration, size, output) = match result {
+            Ok((duration, size)) => (
+                FormattedDuration(*duration).to_string(),
+                HumanBytes(*size as u64).to_string(),
+                output_path.clone()
+            ),
+            Err(e) => ("-".to_string(), "-".to_string(), format!("FAILED: {}", e))
+        };
+        println!("{:>3}  {:<title_width$}  {:>10}  {:>10}  {}", track.index as u32 + 1, track.title, duration, size, output);
+    }
+
+    let failed = results.iter().filter(|r| r.is_err()).count();
+    println!("\n{} of {} tracks rendered successfully.", results.len() - failed, results.len());
+}
+
+pub fn run() {
+    let (options, mode) = get_renderer_options();
+    match mode {
+        RenderMode::Single => {
+            render(options).unwrap();
+        },
+        RenderMode::Batch(template) => render_batch(options, &template),
+        RenderMode::Album(gap_frames) => render_album(options, gap_frames).unwrap()
+    }
 }
\ No newline at end of file
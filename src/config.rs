@@ -2,6 +2,7 @@ use anyhow::{Context, Result};
 use serde::{Serialize, Serializer, Deserialize, Deserializer};
 use tiny_skia::Color;
 use csscolorparser::Color as CssColor;
+use sameboy::{ColorCorrectionMode, DmgPalette, HighpassFilterMode};
 use crate::visualizer::channel_settings::ChannelSettingsManager;
 
 fn serialize_color<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
@@ -65,10 +66,118 @@ impl Default for PianoRollConfig {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "ColorCorrectionMode", rename_all = "kebab-case")]
+enum ColorCorrectionModeDef {
+    Disabled,
+    CorrectCurves,
+    Balanced,
+    BoostContrast,
+    ReduceContrast,
+    LowContrast,
+    Accurate
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "DmgPalette", rename_all = "kebab-case")]
+enum DmgPaletteDef {
+    Grey,
+    Dmg,
+    Mgb,
+    Gbl
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScreenSide {
+    Left,
+    Right
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ScreenConfig {
+    pub enabled: bool,
+    /// Side of the piano roll the screen panel goes on (left is the top in vertical layouts)
+    pub side: ScreenSide,
+    /// Fraction of the piano roll's width (height in vertical layouts) taken by the panel
+    pub size: f32,
+    #[serde(with = "ColorCorrectionModeDef")]
+    pub color_correction: ColorCorrectionMode,
+    #[serde(with = "DmgPaletteDef")]
+    pub dmg_palette: DmgPalette,
+    /// Show the SGB border around the screen on Super Game Boy models
+    pub sgb_border: bool,
+    #[serde(serialize_with = "serialize_color", deserialize_with = "deserialize_color")]
+    pub background_color: Color
+}
+
+impl Default for ScreenConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            side: ScreenSide::Right,
+            size: 0.5,
+            color_correction: ColorCorrectionMode::Balanced,
+            dmg_palette: DmgPalette::Grey,
+            sgb_border: true,
+            background_color: Color::BLACK
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "HighpassFilterMode", rename_all = "kebab-case")]
+enum HighpassFilterModeDef {
+    Off,
+    Accurate,
+    #[serde(rename = "remove-dc")]
+    RemoveDCOffset
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AudioConfig {
+    #[serde(with = "HighpassFilterModeDef")]
+    pub highpass_mode: HighpassFilterMode,
+    /// Volume of the buzz the console's circuitry adds to the output, from 0.0 to 1.0
+    pub interference: f64,
+    /// Sound like a ProSound-modded console, overriding the settings above
+    pub prosound: bool
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            // Same as SameBoy's default, which keeps the DC offset
+            highpass_mode: HighpassFilterMode::Off,
+            interference: 0.0,
+            prosound: false
+        }
+    }
+}
+
+impl AudioConfig {
+    /// The high-pass filter mode and interference volume to give the emulator.
+    /// A ProSound mod taps the APU before the amplifier, so there's no buzz, and only the DC offset gets removed.
+    pub fn emulator_settings(&self) -> (HighpassFilterMode, f64) {
+        match self.prosound {
+            true => (HighpassFilterMode::RemoveDCOffset, 0.0),
+            false => (self.highpass_mode, self.interference.clamp(0.0, 1.0))
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Default, Clone)]
 #[serde(default)]
 pub struct Config {
-    pub piano_roll: PianoRollConfig
+    pub piano_roll: PianoRollConfig,
+    /// Game Boy LCD panel next to the piano roll
+    pub screen: ScreenConfig,
+    /// Emulated audio output circuitry
+    pub audio: AudioConfig,
+    /// Use the Japanese GD3 fields for VGM metadata when they're available
+    pub prefer_japanese_metadata: bool
 }
 
 impl Config {
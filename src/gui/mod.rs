@@ -10,12 +10,13 @@ use indicatif::{FormattedDuration, HumanBytes, HumanDuration};
 use native_dialog::{FileDialog, MessageDialog, MessageType};
 use slint;
 use slint::{Color, Model as _};
-use sameboy::{Model, Revision};
+use sameboy::{HighpassFilterMode, Model, Revision, VideoStandard};
 use crate::config::Config;
 use crate::gui::render_thread::{RenderThreadMessage, RenderThreadRequest};
 use crate::renderer::gbs::Gbs;
-use crate::renderer::{lsdj, m3u_searcher, vgm};
+use crate::renderer::{lsdj, m3u_searcher, vgm, uge, gbt};
 use crate::renderer::render_options::{RendererOptions, RenderInput, StopCondition};
+use crate::video_builder::video_options::{AUDIO_ONLY_EXTENSIONS, is_audio_only_path};
 
 slint::include_modules!();
 
@@ -54,10 +55,12 @@ fn slint_color_component_arr<I: IntoIterator<Item = tiny_skia::Color>>(a: I) ->
 fn browse_for_rom_dialog(for_2x: bool) -> Option<String> {
     let file = if !for_2x {
         FileDialog::new()
-            .add_filter("All supported formats", &["gb", "gbs", "vgm", "vgz", "vgm.gz"])
+            .add_filter("All supported formats", &["gb", "gbs", "vgm", "vgz", "vgm.gz", "uge", "mod"])
             .add_filter("LSDj ROMs", &["gb"])
             .add_filter("GameBoy Sound Files", &["gbs"])
             .add_filter("VGM Log Files", &["vgm", "vgz", "vgm.gz"])
+            .add_filter("hUGETracker Modules", &["uge"])
+            .add_filter("GBT Player MOD Files", &["mod"])
     } else {
         FileDialog::new()
             .add_filter("LSDj ROMs", &["gb"])
@@ -69,13 +72,15 @@ fn browse_for_rom_dialog(for_2x: bool) -> Option<String> {
     }
 }
 
-fn browse_for_sav_dialog() -> Option<String> {
-    let file = FileDialog::new()
+fn browse_for_sav_dialog() -> Option<Vec<String>> {
+    let files = FileDialog::new()
+        .add_filter("All supported formats", &["sav", "lsdsng", "lsdprj"])
         .add_filter("LSDj Saves", &["sav"])
-        .show_open_single_file();
+        .add_filter("LSDj Songs", &["lsdsng", "lsdprj"])
+        .show_open_multiple_file();
 
-    match file {
-        Ok(Some(path)) => Some(path.to_str().unwrap().to_string()),
+    match files {
+        Ok(paths) if !paths.is_empty() => Some(paths.iter().map(|p| p.to_str().unwrap().to_string()).collect()),
         _ => None
     }
 }
@@ -95,10 +100,11 @@ fn browse_for_background_dialog() -> Option<String> {
 
 fn browse_for_video_dialog() -> Option<String> {
     let file = FileDialog::new()
-        .add_filter("All supported formats", &["mp4", "mkv", "mov"])
+        .add_filter("All supported formats", &["mp4", "mkv", "mov", "wav", "flac", "opus"])
         .add_filter("MPEG-4 Video", &["mp4"])
         .add_filter("Matroska Video", &["mkv"])
         .add_filter("QuickTime Video", &["mov"])
+        .add_filter("Audio only", &AUDIO_ONLY_EXTENSIONS)
         .show_save_single_file();
 
     match file {
@@ -171,7 +177,7 @@ pub fn run() {
     main_window.set_ffmpeg_version(crate::video_builder::ffmpeg_version().into());
 
     let options = Rc::new(RefCell::new(RendererOptions::default()));
-    let track_durations: Rc<RefCell<HashMap<u8, Duration>>> = Rc::new(RefCell::new(HashMap::new()));
+    let playlist_entries: Rc<RefCell<HashMap<u8, m3u_searcher::M3uEntry>>> = Rc::new(RefCell::new(HashMap::new()));
 
     {
         let main_window_weak = main_window.as_weak();
@@ -242,6 +248,32 @@ pub fn run() {
     }
     main_window.invoke_update_channel_configs(false);
 
+    {
+        let main_window_weak = main_window.as_weak();
+        let options = options.clone();
+        main_window.on_update_audio_config(move |write_to_config| {
+            let audio_config = &mut options.borrow_mut().config.audio;
+            if write_to_config {
+                audio_config.highpass_mode = match main_window_weak.unwrap().get_highpass_mode_index() {
+                    1 => HighpassFilterMode::Accurate,
+                    2 => HighpassFilterMode::RemoveDCOffset,
+                    _ => HighpassFilterMode::Off
+                };
+                audio_config.interference = main_window_weak.unwrap().get_interference_percent() as f64 / 100.0;
+                audio_config.prosound = main_window_weak.unwrap().get_prosound();
+            } else {
+                main_window_weak.unwrap().set_highpass_mode_index(match audio_config.highpass_mode {
+                    HighpassFilterMode::Off => 0,
+                    HighpassFilterMode::Accurate => 1,
+                    HighpassFilterMode::RemoveDCOffset => 2
+                });
+                main_window_weak.unwrap().set_interference_percent((audio_config.interference * 100.0).round() as i32);
+                main_window_weak.unwrap().set_prosound(audio_config.prosound);
+            }
+        });
+    }
+    main_window.invoke_update_audio_config(false);
+
     {
         let main_window_weak = main_window.as_weak();
         let options = options.clone();
@@ -257,6 +289,7 @@ pub fn run() {
                         Err(e) => return display_error_dialog(&e.to_string())
                     };
                     main_window_weak.unwrap().invoke_update_channel_configs(false);
+                    main_window_weak.unwrap().invoke_update_audio_config(false);
                 },
                 None => ()
             }
@@ -270,6 +303,7 @@ pub fn run() {
             match browse_for_config_export_dialog() {
                 Some(path) => {
                     main_window_weak.unwrap().invoke_update_channel_configs(true);
+                    main_window_weak.unwrap().invoke_update_audio_config(true);
 
                     let config_str = match options.borrow().config.export() {
                         Ok(c) => c,
@@ -292,6 +326,7 @@ pub fn run() {
         main_window.on_reset_config(move || {
             options.borrow_mut().config = Config::default();
             main_window_weak.unwrap().invoke_update_channel_configs(false);
+            main_window_weak.unwrap().invoke_update_audio_config(false);
         });
     }
 
@@ -384,11 +419,11 @@ pub fn run() {
     {
         let main_window_weak = main_window.as_weak();
         let options = options.clone();
-        let track_durations = track_durations.clone();
+        let playlist_entries = playlist_entries.clone();
         main_window.on_browse_for_rom(move |for_2x| {
             match browse_for_rom_dialog(for_2x) {
                 Some(path) => {
-                    track_durations.borrow_mut().clear();
+                    playlist_entries.borrow_mut().clear();
 
                     if !for_2x {
                         main_window_weak.unwrap().set_rom_path(path.clone().into());
@@ -460,7 +495,7 @@ pub fn run() {
                             gbs.title().unwrap(), gbs.artist().unwrap(), gbs.copyright().unwrap(),
                             gbs.song_count(), gbs.starting_song()
                         );
-                        let m3u_titles = match m3u_searcher::search(path.clone()) {
+                        let m3u_entries = match m3u_searcher::search(path.clone()) {
                             Ok(t) => t,
                             Err(e) => {
                                 println!("M3U search failed: {}", e);
@@ -469,12 +504,10 @@ pub fn run() {
                         };
                         let track_titles: Vec<String> = (0..gbs.song_count())
                             .map(|i| {
-                                match m3u_titles.get(&i) {
-                                    Some((title, duration)) => {
-                                        if duration.is_some() {
-                                            track_durations.borrow_mut().insert(i, duration.unwrap().clone());
-                                        }
-                                        format!("Track {}: {}", i + 1, title.clone())
+                                match m3u_entries.get(&i) {
+                                    Some(entry) => {
+                                        playlist_entries.borrow_mut().insert(i, entry.clone());
+                                        format!("Track {}: {}", i + 1, entry.title)
                                     },
                                     None => format!("Track {}", i + 1)
                                 }
@@ -506,11 +539,41 @@ pub fn run() {
                         return;
                     }
 
+                    let uge_s = uge::Uge::open(path.clone());
+                    if let Ok(uge_s) = uge_s {
+                        let song_title = match uge_s.name.is_empty() {
+                            false => uge_s.name,
+                            true => "<?>".to_string()
+                        };
+                        main_window_weak.unwrap().set_track_titles(slint_string_arr(vec![song_title]));
+
+                        main_window_weak.unwrap().set_input_valid(true);
+                        main_window_weak.unwrap().set_input_type(SongInputType::UGE);
+                        options.borrow_mut().input = RenderInput::UGE(path.clone());
+                        return;
+                    }
+
+                    let gbt_s = gbt::Module::open(path.clone());
+                    if let Ok(gbt_s) = gbt_s {
+                        let song_title = match gbt_s.title.is_empty() {
+                            false => gbt_s.title,
+                            true => "<?>".to_string()
+                        };
+                        main_window_weak.unwrap().set_track_titles(slint_string_arr(vec![song_title]));
+
+                        main_window_weak.unwrap().set_input_valid(true);
+                        main_window_weak.unwrap().set_input_type(SongInputType::GBT);
+                        options.borrow_mut().input = RenderInput::GBT(path.clone());
+                        return;
+                    }
+
                     display_error_dialog(format!(
-                        "Unrecognized input file!\n\nWhile opening as LSDj ROM: {}\nWhile opening as GBS: {}\nWhile opening as VGM: {}",
+                        "Unrecognized input file!\n\nWhile opening as LSDj ROM: {}\nWhile opening as GBS: {}\nWhile opening as VGM: {}\nWhile opening as UGE: {}\nWhile opening as MOD: {}",
                         lsdj_version.err().unwrap(),
                         gbs.err().unwrap(),
-                        vgm_s.err().unwrap()
+                        vgm_s.err().unwrap(),
+                        uge_s.err().unwrap(),
+                        gbt_s.err().unwrap()
                     ).as_str());
                     main_window_weak.unwrap().set_rom_path("".into());
                     options.borrow_mut().input = RenderInput::None;
@@ -540,7 +603,18 @@ pub fn run() {
         let options = options.clone();
         main_window.on_browse_for_sav(move |for_2x| {
             match browse_for_sav_dialog() {
-                Some(path) => {
+                Some(paths) => {
+                    let is_songs = paths.iter().all(lsdj::is_song_file);
+                    if !is_songs && paths.len() > 1 {
+                        display_error_dialog("Please select either a single LSDj save or one or more LSDj songs.");
+                        return;
+                    }
+                    if is_songs && (for_2x || main_window_weak.unwrap().invoke_is_2x()) {
+                        display_error_dialog("2x LSDj rendering requires save files. Please select a .sav file.");
+                        return;
+                    }
+                    let path = paths.join(", ");
+
                     if !for_2x {
                         main_window_weak.unwrap().set_sav_path(path.clone().into());
                         main_window_weak.unwrap().set_track_titles(slint::ModelRc::new(slint::VecModel::from(Vec::new())));
@@ -559,13 +633,25 @@ pub fn run() {
                     main_window_weak.unwrap().set_track_duration_type("seconds".into());
                     main_window_weak.unwrap().invoke_update_formatted_duration();
 
-                    match lsdj::get_track_titles_from_save(path.clone()) {
+                    let track_titles = if is_songs {
+                        lsdj::build_sram_from_songs(&paths)
+                            .and_then(|sram| lsdj::get_track_titles_from_sram(&sram))
+                    } else {
+                        lsdj::get_track_titles_from_save(path.clone())
+                    };
+
+                    match track_titles {
                         Ok(track_titles) => {
                             main_window_weak.unwrap().set_input_type(SongInputType::LSDj);
                             main_window_weak.unwrap().set_input_valid(true);
 
                             if !for_2x {
-                                if !main_window_weak.unwrap().invoke_is_2x() {
+                                if is_songs {
+                                    options.borrow_mut().input = RenderInput::LSDjSongs(
+                                        main_window_weak.unwrap().get_rom_path().to_string(),
+                                        paths.clone()
+                                    );
+                                } else if !main_window_weak.unwrap().invoke_is_2x() {
                                     options.borrow_mut().input = RenderInput::LSDj(
                                         main_window_weak.unwrap().get_rom_path().to_string(),
                                         path.clone()
@@ -613,6 +699,11 @@ pub fn run() {
                 _ => return false
             }
 
+            if is_2x && matches!(options.borrow().input, RenderInput::LSDjSongs(_, _)) {
+                display_error_dialog("2x LSDj rendering requires save files. Please select a .sav file.");
+                return false;
+            }
+
             let rom_path = main_window_weak.unwrap().get_rom_path().to_string();
             let sav_path = main_window_weak.unwrap().get_sav_path().to_string();
 
@@ -645,6 +736,7 @@ pub fn run() {
     {
         let main_window_weak = main_window.as_weak();
         let options = options.clone();
+        let playlist_entries = playlist_entries.clone();
         main_window.on_update_formatted_duration(move || {
             if main_window_weak.unwrap().get_selected_track_index() == -1 {
                 main_window_weak.unwrap().set_track_duration_formatted("<unknown>".into());
@@ -661,25 +753,59 @@ pub fn run() {
                 "seconds" => format!("time:{}", new_duration_num),
                 "frames" => format!("frames:{}", new_duration_num),
                 "loops" => format!("loops:{}", new_duration_num),
+                "silence" => format!("silence:{}", new_duration_num),
+                "playlist" => "time:m3u".to_string(),
                 _ => unreachable!()
             };
             if let Ok(stop_condition) = StopCondition::from_str(&stop_condition_str) {
                 options.borrow_mut().stop_condition = stop_condition;
 
+                let frame_rate = options.borrow().frame_rate();
                 let label = match stop_condition {
+                    StopCondition::Time(seconds) => FormattedDuration(Duration::from_secs_f64(seconds)).to_string(),
                     StopCondition::Frames(frames) => {
-                        let seconds = frames as f64 / 60.0;
+                        let seconds = frames as f64 / frame_rate;
                         FormattedDuration(Duration::from_secs_f64(seconds)).to_string()
                     },
                     StopCondition::Loops(loops) => {
-                        if let RenderInput::VGM(vgm_path, engine_rate, _) = options.borrow().input.clone() {
+                        if let RenderInput::VGM(vgm_path, engine_rate, tma_offset) = options.borrow().input.clone() {
                             let vgm_s = vgm::Vgm::open(vgm_path).unwrap();
-                            let frames = vgm::duration_frames(&vgm_s, engine_rate,loops);
-                            let seconds = frames as f64 / 60.0;
+                            let frames = match options.borrow().vgm_direct {
+                                true => vgm::direct_duration_frames(&vgm_s, frame_rate, loops),
+                                false => vgm::duration_frames(&vgm_s, engine_rate, tma_offset, loops)
+                            };
+                            let seconds = frames as f64 / frame_rate;
                             FormattedDuration(Duration::from_secs_f64(seconds)).to_string()
+                        } else if let RenderInput::UGE(uge_path) = options.borrow().input.clone() {
+                            match uge::Uge::open(uge_path).and_then(|s| uge::duration_frames(&s, loops)) {
+                                Ok(frames) => {
+                                    let seconds = frames as f64 / frame_rate;
+                                    FormattedDuration(Duration::from_secs_f64(seconds)).to_string()
+                                },
+                                Err(_) => "<error>".to_string()
+                            }
+                        } else if let RenderInput::GBT(mod_path) = options.borrow().input.clone() {
+                            match gbt::Module::open(mod_path).and_then(|m| gbt::duration_frames(&m, loops)) {
+                                Ok(frames) => {
+                                    let seconds = frames as f64 / frame_rate;
+                                    FormattedDuration(Duration::from_secs_f64(seconds)).to_string()
+                                },
+                                Err(_) => "<error>".to_string()
+                            }
                         } else {
                             "<unknown>".to_string()
                         }
+                    },
+                    StopCondition::Silence { seconds, .. } => {
+                        format!("{} of silence", FormattedDuration(Duration::from_secs_f64(seconds)))
+                    },
+                    StopCondition::Playlist => {
+                        let track_index = main_window_weak.unwrap().get_selected_track_index() as u8;
+                        match playlist_entries.borrow().get(&track_index).and_then(|e| e.play_length().map(|l| (l, e.fade))) {
+                            Some((play_length, Some(fade))) => format!("{} + {} fade", FormattedDuration(play_length), FormattedDuration(fade)),
+                            Some((play_length, None)) => FormattedDuration(play_length).to_string(),
+                            None => "<error>".to_string()
+                        }
                     }
                 };
                 main_window_weak.unwrap().set_track_duration_formatted(label.into());
@@ -730,19 +856,36 @@ pub fn run() {
                 }
             }
 
+            options.borrow_mut().video_options.audio_only = is_audio_only_path(&output_path);
             options.borrow_mut().video_options.output_path = output_path;
 
             let stop_condition = options.borrow().stop_condition.clone();
             let render_input = options.borrow().input.clone();
             match stop_condition {
                 StopCondition::Loops(loops) => {
-                    if main_window_weak.unwrap().get_input_type() == SongInputType::GBS {
-                        display_error_dialog("Loop detection is not supported for GBS files. Please select a different duration type.");
-                        return;
-                    } else if let RenderInput::VGM(vgm_path, engine_rate, _) = render_input {
+                    if let RenderInput::VGM(vgm_path, engine_rate, tma_offset) = render_input {
                         let vgm_s = vgm::Vgm::open(vgm_path).unwrap();
-                        let frames = vgm::duration_frames(&vgm_s, engine_rate,loops);
+                        let frames = match options.borrow().vgm_direct {
+                            true => vgm::direct_duration_frames(&vgm_s, options.borrow().frame_rate(), loops),
+                            false => vgm::duration_frames(&vgm_s, engine_rate, tma_offset, loops)
+                        };
                         options.borrow_mut().stop_condition = StopCondition::Frames(frames as u64);
+                    } else if let RenderInput::UGE(uge_path) = render_input {
+                        match uge::Uge::open(uge_path).and_then(|s| uge::duration_frames(&s, loops)) {
+                            Ok(frames) => options.borrow_mut().stop_condition = StopCondition::Frames(frames as u64),
+                            Err(e) => {
+                                display_error_dialog(format!("Failed to determine UGE song length! {}", e).as_str());
+                                return;
+                            }
+                        }
+                    } else if let RenderInput::GBT(mod_path) = render_input {
+                        match gbt::Module::open(mod_path).and_then(|m| gbt::duration_frames(&m, loops)) {
+                            Ok(frames) => options.borrow_mut().stop_condition = StopCondition::Frames(frames as u64),
+                            Err(e) => {
+                                display_error_dialog(format!("Failed to determine MOD song length! {}", e).as_str());
+                                return;
+                            }
+                        }
                     }
                 },
                 _ => ()
@@ -784,10 +927,14 @@ pub fn run() {
                 "CGB-E" => Model::CGB(Revision::RevE),
                 "MGB" => Model::MGB,
                 "AGB" => Model::AGB,
+                "SGB" => Model::SGB(VideoStandard::NTSC, true),
+                "SGB-PAL" => Model::SGB(VideoStandard::PAL, true),
+                "SGB2" => Model::SGB2(true),
                 _ => unreachable!()
             };
 
             main_window_weak.unwrap().invoke_update_channel_configs(true);
+            main_window_weak.unwrap().invoke_update_audio_config(true);
 
             if main_window_weak.unwrap().get_background_path().is_empty() {
                 options.borrow_mut().video_options.background_path = None;
@@ -0,0 +1,55 @@
+use anyhow::{Result, anyhow, bail};
+use std::time::Duration;
+use crate::renderer::gbs::Gbs;
+use crate::renderer::{lsdj, m3u_searcher};
+use crate::renderer::render_options::RenderInput;
+
+#[derive(Clone)]
+pub struct AlbumTrack {
+    pub index: u8,
+    pub title: String
+}
+
+pub struct Chapter {
+    pub title: String,
+    pub start: Duration,
+    pub end: Duration
+}
+
+/// Every track playable from a GBS or single-console LSDj input, titled the same way the GUI lists them
+pub fn tracks(input: &RenderInput) -> Result<Vec<AlbumTrack>> {
+    let titles: Vec<Option<String>> = match input {
+        RenderInput::GBS(gbs_path) => {
+            let gbs = Gbs::open(gbs_path).map_err(|e| anyhow!("Failed to read GBS! {}", e))?;
+            let m3u_entries = match m3u_searcher::search(gbs_path) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    println!("M3U search failed: {}", e);
+                    Default::default()
+                }
+            };
+            (0..gbs.song_count())
+                .map(|i| m3u_entries.get(&i).map(|e| e.title.clone()))
+                .collect()
+        },
+        RenderInput::LSDj(_, sav_path) => lsdj::get_track_titles_from_save(sav_path)?
+            .into_iter()
+            .map(Some)
+            .collect(),
+        RenderInput::LSDjSongs(_, song_paths) => lsdj::build_sram_from_songs(song_paths)
+            .and_then(|sram| lsdj::get_track_titles_from_sram(&sram))?
+            .into_iter()
+            .map(Some)
+            .collect(),
+        _ => bail!("Only GBS files and LSDj saves/songs can be rendered as an album!")
+    };
+
+    Ok(titles
+        .into_iter()
+        .enumerate()
+        .map(|(i, title)| AlbumTrack {
+            index: i as u8,
+            title: title.unwrap_or(format!("Track {}", i + 1))
+        })
+        .collect())
+}
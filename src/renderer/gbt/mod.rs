@@ -0,0 +1,19 @@
+mod module;
+mod player;
+
+use anyhow::Result;
+use crate::renderer::vgm::converter;
+
+pub use module::Module;
+
+pub fn gbt_to_gbs(module: &Module) -> Result<Vec<u8>> {
+    let output = player::run(module)?;
+    let engine_data = converter::commands_to_engine_format(output.commands, false, false)?;
+
+    Ok(converter::engine_data_to_gbs(&engine_data, 0, 0, &module.title, "", ""))
+}
+
+pub fn duration_frames(module: &Module, loops: usize) -> Result<usize> {
+    let output = player::run(module)?;
+    Ok(output.intro_ticks + (loops * output.loop_ticks))
+}
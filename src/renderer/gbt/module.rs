@@ -0,0 +1,100 @@
+use anyhow::{Result, ensure, bail};
+use std::fs;
+use std::path::Path;
+
+pub const PATTERN_ROWS: usize = 64;
+pub const CHANNEL_COUNT: usize = 4;
+
+const SAMPLE_COUNT: usize = 31;
+const HEADER_SIZE: usize = 1084;
+const PATTERN_SIZE: usize = PATTERN_ROWS * CHANNEL_COUNT * 4;
+const PERIOD_COUNT: i32 = 72;
+
+#[derive(Copy, Clone, Default)]
+pub struct Cell {
+    pub note: Option<i32>,
+    pub sample: u8,
+    pub effect: u8,
+    pub param: u8
+}
+
+pub struct Module {
+    pub title: String,
+    pub sample_volumes: [u8; SAMPLE_COUNT],
+    pub orders: Vec<usize>,
+    pub patterns: Vec<Vec<[Cell; CHANNEL_COUNT]>>
+}
+
+fn period_to_note(period: u16) -> Result<i32> {
+    // Same 6 octave period table as mod2gbt, starting at C-0 (period 1712) which becomes C-3 in GBT Player
+    let (note, error) = (0..PERIOD_COUNT)
+        .map(|n| (n, (1712.0 / 2f64.powf(n as f64 / 12.0) - period as f64).abs()))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap();
+    ensure!(error < 2.0, "Note with period {} is out of range!", period);
+    Ok(note)
+}
+
+impl Module {
+    pub fn new(data: &[u8]) -> Result<Self> {
+        ensure!(data.len() >= HEADER_SIZE, "MOD file is too small!");
+        match &data[1080..1084] {
+            b"M.K." | b"M!K!" | b"4CHN" | b"FLT4" => (),
+            _ => bail!("Not a 4 channel MOD file!")
+        }
+
+        let title = String::from_utf8_lossy(&data[0..20])
+            .trim_end_matches('\0')
+            .trim()
+            .to_string();
+
+        let mut sample_volumes = [0u8; SAMPLE_COUNT];
+        for (i, volume) in sample_volumes.iter_mut().enumerate() {
+            *volume = data[20 + (30 * i) + 25].min(64);
+        }
+
+        let song_length = data[950] as usize;
+        ensure!((1..=128).contains(&song_length), "MOD has an invalid song length!");
+        let orders: Vec<usize> = data[952..(952 + song_length)].iter().map(|&o| o as usize).collect();
+
+        // Unused patterns can still be stored, so count every entry of the order table
+        let pattern_count = data[952..1080].iter().cloned().max().unwrap() as usize + 1;
+        ensure!(data.len() >= HEADER_SIZE + (pattern_count * PATTERN_SIZE), "MOD pattern data is truncated!");
+
+        let mut patterns: Vec<Vec<[Cell; CHANNEL_COUNT]>> = Vec::with_capacity(pattern_count);
+        for raw_pattern in data[HEADER_SIZE..].chunks_exact(PATTERN_SIZE).take(pattern_count) {
+            let mut rows: Vec<[Cell; CHANNEL_COUNT]> = Vec::with_capacity(PATTERN_ROWS);
+            for raw_row in raw_pattern.chunks_exact(CHANNEL_COUNT * 4) {
+                let mut row = [Cell::default(); CHANNEL_COUNT];
+                for (cell, raw_cell) in row.iter_mut().zip(raw_row.chunks_exact(4)) {
+                    let period = (((raw_cell[0] & 0x0F) as u16) << 8) | raw_cell[1] as u16;
+                    cell.note = match period {
+                        0 => None,
+                        period => Some(period_to_note(period)?)
+                    };
+                    cell.sample = (raw_cell[0] & 0xF0) | (raw_cell[2] >> 4);
+                    cell.effect = raw_cell[2] & 0x0F;
+                    cell.param = raw_cell[3];
+                }
+                rows.push(row);
+            }
+            patterns.push(rows);
+        }
+
+        Ok(Self {
+            title,
+            sample_volumes,
+            orders,
+            patterns
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = fs::read(path)?;
+        Self::new(&data)
+    }
+
+    pub fn cell(&self, order: usize, row: usize, channel: usize) -> Cell {
+        self.patterns[self.orders[order]][row][channel]
+    }
+}
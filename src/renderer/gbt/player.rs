@@ -0,0 +1,266 @@
+use anyhow::{Result, bail};
+use std::ops::RangeInclusive;
+use crate::renderer::tracker::{self, TrackerPlayer, PlayerOutput, note_period};
+use super::module::{Module, PATTERN_ROWS, CHANNEL_COUNT};
+
+// Port of the GBT Player playback routine, following the subset of MOD features mod2gbt converts. Notes use
+// GBT Player's period table, and samples map to instruments the same way as in its template MOD.
+
+const DEFAULT_SPEED: u32 = 6;
+
+const NR30: u16 = 0xFF1A;
+const NR50: u16 = 0xFF24;
+const NR51: u16 = 0xFF25;
+const NR52: u16 = 0xFF26;
+const WAVE_RAM: u16 = 0xFF30;
+
+const CHANNEL_BASES: [u16; CHANNEL_COUNT] = [0xFF10, 0xFF15, 0xFF1A, 0xFF1F];
+// Duties are samples 1-4, waves 8-15 and noises 16-31
+const SAMPLE_RANGES: [RangeInclusive<u8>; CHANNEL_COUNT] = [1..=4, 1..=4, 8..=15, 16..=31];
+
+const WAVES: [[u8; 16]; 8] = [
+    [0xA5, 0xD7, 0xC9, 0xE1, 0xBC, 0x9A, 0x76, 0x31, 0x0C, 0xBA, 0xDE, 0x60, 0x1B, 0xCA, 0x03, 0x93],
+    [0xF0, 0xE1, 0xD2, 0xC3, 0xB4, 0xA5, 0x96, 0x87, 0x78, 0x69, 0x5A, 0x4B, 0x3C, 0x2D, 0x1E, 0x0F],
+    [0xFD, 0xEC, 0xDB, 0xCA, 0xB9, 0xA8, 0x97, 0x86, 0x79, 0x68, 0x57, 0x46, 0x35, 0x24, 0x13, 0x02],
+    [0xDE, 0xFE, 0xDC, 0xBA, 0x9A, 0xA9, 0x87, 0x77, 0x88, 0x87, 0x65, 0x56, 0x54, 0x32, 0x10, 0x12],
+    [0xAB, 0xCD, 0xEF, 0xED, 0xCB, 0xA0, 0x12, 0x3E, 0xDC, 0xBA, 0xBC, 0xDE, 0xFE, 0xDC, 0x32, 0x10],
+    [0xFF, 0xEE, 0xDD, 0xCC, 0xBB, 0xAA, 0x99, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00],
+    [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x79, 0xBC, 0xDE, 0xEF, 0xFF, 0xEE, 0xDC, 0xB9, 0x75, 0x43, 0x21, 0x10, 0x00, 0x11, 0x23, 0x45]
+];
+
+const NOISE: [u8; 16] = [
+    // 7 bit
+    0x5F, 0x5B, 0x4B, 0x2F, 0x3B, 0x58, 0x1F, 0x0F,
+    // 15 bit
+    0x90, 0x80, 0x70, 0x50, 0x00, 0x67, 0x63, 0x53
+];
+
+#[derive(Copy, Clone, Default)]
+struct ChannelState {
+    note: i32,
+    instrument: u8,
+    volume: u8,
+    playing: bool,
+    last_period: Option<i32>,
+
+    arpeggio: Option<(i32, i32)>,
+    cut_tick: Option<u32>
+}
+
+struct Player<'a> {
+    module: &'a Module,
+    channels: [ChannelState; CHANNEL_COUNT],
+    speed: u32,
+    tick: u32,
+    order: usize,
+    row: usize,
+    next_position: Option<(usize, usize)>,
+    panning: u8,
+    wave: Option<u8>,
+    writes: Vec<(u16, u8)>
+}
+
+impl<'a> Player<'a> {
+    fn new(module: &'a Module) -> Result<Self> {
+        for order in 0..module.orders.len() {
+            for row in 0..PATTERN_ROWS {
+                for channel in 0..CHANNEL_COUNT {
+                    let sample = module.cell(order, row, channel).sample;
+                    let range = &SAMPLE_RANGES[channel];
+                    if sample != 0 && !range.contains(&sample) {
+                        bail!(
+                            "Channel {} uses sample {} at order {} row {}, but only samples {}-{} are supported on this channel!",
+                            channel + 1, sample, order, row, range.start(), range.end()
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            module,
+            channels: Default::default(),
+            speed: DEFAULT_SPEED,
+            tick: 0,
+            order: 0,
+            row: 0,
+            next_position: None,
+            panning: 0xFF,
+            wave: None,
+            writes: vec![(NR52, 0x80), (NR50, 0x77), (NR51, 0xFF)]
+        })
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.writes.push((addr, val));
+    }
+
+    fn process_row(&mut self, channel: usize) {
+        let cell = self.module.cell(self.order, self.row, channel);
+
+        let state = &mut self.channels[channel];
+        state.arpeggio = None;
+        state.cut_tick = None;
+
+        if cell.sample != 0 {
+            state.instrument = cell.sample - SAMPLE_RANGES[channel].start();
+            state.volume = self.module.sample_volumes[cell.sample as usize - 1];
+        }
+
+        let mut retrigger = false;
+        if let Some(note) = cell.note {
+            state.note = note;
+            state.playing = true;
+            retrigger = true;
+        }
+
+        match cell.effect {
+            0x0 if cell.param != 0 => {
+                state.arpeggio = Some(((cell.param >> 4) as i32, (cell.param & 0xF) as i32));
+            }
+            0xB => self.next_position = Some((cell.param as usize, 0)),
+            0xC => {
+                state.volume = cell.param.min(64);
+                retrigger = state.playing;
+            }
+            0xD => {
+                // The row is stored in decimal
+                let row = ((cell.param >> 4) * 10 + (cell.param & 0xF)) as usize;
+                self.next_position = Some((self.order + 1, row));
+            }
+            0xE => match cell.param >> 4 {
+                0x8 => {
+                    let mask = (0x11u8) << channel;
+                    let bits = match cell.param & 0xF {
+                        0x0..=0x3 => 0x10 << channel,
+                        0xC..=0xF => 0x01 << channel,
+                        _ => mask
+                    };
+                    self.panning = (self.panning & !mask) | bits;
+                    let panning = self.panning;
+                    self.write(NR51, panning);
+                }
+                0xC => state.cut_tick = Some((cell.param & 0xF) as u32),
+                _ => ()
+            },
+            // BPM changes (>= $20) can't be done on a fixed tick rate
+            0xF if (1..0x20).contains(&cell.param) => self.speed = cell.param as u32,
+            _ => ()
+        }
+
+        if retrigger {
+            self.trigger(channel);
+        }
+    }
+
+    fn trigger(&mut self, channel: usize) {
+        let base = CHANNEL_BASES[channel];
+        let state = self.channels[channel];
+        // mod2gbt's conversion: 64 is full volume, everything else drops the low bits
+        let volume = (state.volume >> 2).min(15);
+        let period = note_period(state.note);
+
+        match channel {
+            0 | 1 => {
+                self.write(base + 1, (state.instrument & 3) << 6);
+                self.write(base + 2, volume << 4);
+                self.write(base + 3, (period & 0xFF) as u8);
+                self.write(base + 4, 0x80 | (period >> 8) as u8);
+                self.channels[channel].last_period = Some(period);
+            }
+            2 => {
+                let wave = state.instrument & 7;
+                if self.wave != Some(wave) {
+                    // The wave channel's DAC has to be off while wave RAM is written
+                    self.write(NR30, 0x00);
+                    for (i, &sample) in WAVES[wave as usize].iter().enumerate() {
+                        self.write(WAVE_RAM + i as u16, sample);
+                    }
+                    self.wave = Some(wave);
+                }
+                let output_level = match volume {
+                    0..=3 => 0,
+                    4..=7 => 3,
+                    8..=11 => 2,
+                    _ => 1
+                };
+                self.write(NR30, 0x80);
+                self.write(base + 2, output_level << 5);
+                self.write(base + 3, (period & 0xFF) as u8);
+                self.write(base + 4, 0x80 | (period >> 8) as u8);
+                self.channels[channel].last_period = Some(period);
+            }
+            _ => {
+                self.write(base + 2, volume << 4);
+                self.write(base + 3, NOISE[(state.instrument & 0xF) as usize]);
+                self.write(base + 4, 0x80);
+            }
+        }
+    }
+
+    fn process_tick(&mut self, channel: usize) {
+        let base = CHANNEL_BASES[channel];
+        let state = self.channels[channel];
+
+        if state.cut_tick == Some(self.tick) {
+            self.channels[channel].cut_tick = None;
+            self.channels[channel].playing = false;
+            match channel {
+                2 => self.write(NR30, 0x00),
+                _ => self.write(base + 2, 0x00)
+            }
+            return;
+        }
+
+        if let (Some((x, y)), Some(last_period), true) = (state.arpeggio, state.last_period, channel < 3) {
+            let offset = [0, x, y][(self.tick % 3) as usize];
+            let period = note_period(state.note + offset);
+            if period != last_period {
+                self.channels[channel].last_period = Some(period);
+                self.write(base + 3, (period & 0xFF) as u8);
+                self.write(base + 4, (period >> 8) as u8);
+            }
+        }
+    }
+}
+
+impl<'a> TrackerPlayer for Player<'a> {
+    fn row_start(&self) -> Option<(usize, usize)> {
+        match self.tick {
+            0 => Some((self.order, self.row)),
+            _ => None
+        }
+    }
+
+    fn step(&mut self) {
+        if self.tick == 0 {
+            for channel in 0..CHANNEL_COUNT {
+                self.process_row(channel);
+            }
+        }
+        for channel in 0..CHANNEL_COUNT {
+            self.process_tick(channel);
+        }
+
+        self.tick += 1;
+        if self.tick >= self.speed {
+            self.tick = 0;
+
+            let (order, row) = self.next_position.take().unwrap_or(match self.row + 1 {
+                PATTERN_ROWS => (self.order + 1, 0),
+                row => (self.order, row)
+            });
+            self.order = if order < self.module.orders.len() { order } else { 0 };
+            self.row = row.min(PATTERN_ROWS - 1);
+        }
+    }
+
+    fn take_writes(&mut self) -> Vec<(u16, u8)> {
+        std::mem::take(&mut self.writes)
+    }
+}
+
+pub fn run(module: &Module) -> Result<PlayerOutput> {
+    tracker::record(&mut Player::new(module)?)
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use sameboy::{Gameboy, DirectAccessType};
+
+/// How long the state has to stay unchanged, with silent output, before the driver counts as stopped
+const END_SECONDS: f64 = 5.0;
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct DetectedLoop {
+    pub start: u64,
+    pub length: u64
+}
+
+/// Finds loops in GBS playback by fingerprinting the APU registers and the driver's RAM every
+/// frame. Once a fingerprint repeats the driver is back in a state it has already been in, so
+/// playback from then on is an exact repeat. Frames where nothing changed (e.g. between ticks of
+/// a driver running below the frame rate) are skipped.
+pub struct LoopDetector {
+    fingerprints: HashMap<u64, u64>,
+    detected_loop: Option<DetectedLoop>,
+    last_fingerprint: Option<u64>,
+    unchanged_frames: u64,
+    end_frames: u64,
+    ended: bool
+}
+
+impl LoopDetector {
+    pub fn new(frame_rate: f64) -> Self {
+        Self {
+            fingerprints: HashMap::new(),
+            detected_loop: None,
+            last_fingerprint: None,
+            unchanged_frames: 0,
+            end_frames: (END_SECONDS * frame_rate).round() as u64,
+            ended: false
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.fingerprints.clear();
+        self.detected_loop = None;
+        self.last_fingerprint = None;
+        self.unchanged_frames = 0;
+        self.ended = false;
+    }
+
+    pub fn detected_loop(&self) -> Option<DetectedLoop> {
+        self.detected_loop
+    }
+
+    /// The state stopped changing for several seconds of silence, which means the driver has stopped
+    pub fn ended(&self) -> bool {
+        self.ended
+    }
+
+    /// Fingerprint the state after `frame` frames, `silent` being whether the output is currently
+    /// silent. Returns true if this frame is a loop boundary.
+    pub fn update(&mut self, gb: &mut Gameboy, frame: u64, silent: bool) -> bool {
+        if self.ended {
+            return false;
+        }
+
+        if let Some(detected_loop) = self.detected_loop {
+            return frame > detected_loop.start && (frame - detected_loop.start) % detected_loop.length == 0;
+        }
+
+        let fingerprint = Self::fingerprint(gb);
+        if self.last_fingerprint == Some(fingerprint) {
+            self.unchanged_frames = if silent { self.unchanged_frames + 1 } else { 0 };
+            self.ended = self.unchanged_frames >= self.end_frames;
+            return false;
+        }
+        self.last_fingerprint = Some(fingerprint);
+        self.unchanged_frames = 0;
+
+        match self.fingerprints.get(&fingerprint).cloned() {
+            Some(start) => {
+                self.detected_loop = Some(DetectedLoop {
+                    start,
+                    length: frame - start
+                });
+                self.fingerprints.clear();
+                true
+            }
+            None => {
+                self.fingerprints.insert(fingerprint, frame);
+                false
+            }
+        }
+    }
+
+    fn fingerprint(gb: &mut Gameboy) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        // Sound registers and wave RAM ($FF10-$FF3F)
+        hasher.write(&gb.get_io_registers()[0x10..0x40]);
+        if let Ok(ram) = gb.direct_access(DirectAccessType::RAM) {
+            hasher.write(&ram);
+        }
+        if let Ok(hram) = gb.direct_access(DirectAccessType::HRAM) {
+            hasher.write(&hram);
+        }
+
+        hasher.finish()
+    }
+}
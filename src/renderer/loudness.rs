@@ -0,0 +1,173 @@
+use std::f64::consts::PI;
+
+const BLOCK_SUBDIVISIONS: usize = 4;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+const TRUE_PEAK_CEILING: f64 = -1.0;
+
+const OVERSAMPLING: usize = 4;
+const INTERPOLATOR_TAPS: usize = 12;
+
+#[derive(Copy, Clone)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS, None if everything was below the absolute gate
+    pub integrated: Option<f64>,
+    /// True peak in dBTP
+    pub true_peak: f64
+}
+
+impl Loudness {
+    /// Gain in dB that brings the integrated loudness to `target`, lowered if needed so the true peak stays below -1 dBTP
+    pub fn normalization_gain(&self, target: f64) -> f64 {
+        match self.integrated {
+            Some(integrated) => (target - integrated).min(TRUE_PEAK_CEILING - self.true_peak),
+            None => 0.0
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2]
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The two K-weighting stages from ITU-R BS.1770, recalculated for any sample rate
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2]
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2]
+    };
+
+    [shelf, high_pass]
+}
+
+/// Hann-windowed sinc, one set of taps per oversampled phase
+fn interpolator() -> Vec<[f64; INTERPOLATOR_TAPS]> {
+    let length = (OVERSAMPLING * INTERPOLATOR_TAPS) as f64;
+    (0..OVERSAMPLING)
+        .map(|phase| {
+            let mut taps = [0.0; INTERPOLATOR_TAPS];
+            for (i, tap) in taps.iter_mut().enumerate() {
+                let n = (i * OVERSAMPLING + phase) as f64;
+                let x = (n - length / 2.0) / OVERSAMPLING as f64;
+                let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+                let window = 0.5 - 0.5 * (2.0 * PI * n / length).cos();
+                *tap = sinc * window;
+            }
+            taps
+        })
+        .collect()
+}
+
+struct ChannelState {
+    filters: [Biquad; 2],
+    history: [f64; INTERPOLATOR_TAPS]
+}
+
+/// Measures integrated loudness (EBU R128, gated) and true peak of interleaved 16-bit audio
+pub struct LoudnessMeter {
+    channels: Vec<ChannelState>,
+    interpolator: Vec<[f64; INTERPOLATOR_TAPS]>,
+    subblock_length: usize,
+
+    subblock_energy: f64,
+    subblock_samples: usize,
+    subblocks: Vec<f64>,
+    peak: f64
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            channels: (0..channels)
+                .map(|_| ChannelState {
+                    filters: k_weighting(sample_rate as f64),
+                    history: [0.0; INTERPOLATOR_TAPS]
+                })
+                .collect(),
+            interpolator: interpolator(),
+            // Gating blocks are 400ms long and overlap by 75%
+            subblock_length: sample_rate as usize / 10,
+            subblock_energy: 0.0,
+            subblock_samples: 0,
+            subblocks: Vec::new(),
+            peak: 0.0
+        }
+    }
+
+    pub fn push(&mut self, audio: &[i16]) {
+        for frame in audio.chunks_exact(self.channels.len()) {
+            for (channel, &sample) in self.channels.iter_mut().zip(frame) {
+                let x = sample as f64 / 32768.0;
+
+                channel.history.rotate_left(1);
+                channel.history[INTERPOLATOR_TAPS - 1] = x;
+                for taps in self.interpolator.iter() {
+                    let y: f64 = taps.iter().zip(channel.history.iter().rev()).map(|(t, h)| t * h).sum();
+                    self.peak = self.peak.max(y.abs());
+                }
+                self.peak = self.peak.max(x.abs());
+
+                let weighted = channel.filters.iter_mut().fold(x, |x, filter| filter.process(x));
+                self.subblock_energy += weighted * weighted;
+            }
+
+            self.subblock_samples += 1;
+            if self.subblock_samples == self.subblock_length {
+                self.subblocks.push(self.subblock_energy / self.subblock_length as f64);
+                self.subblock_energy = 0.0;
+                self.subblock_samples = 0;
+            }
+        }
+    }
+
+    pub fn loudness(&self) -> Loudness {
+        let block_loudness = |energy: f64| -0.691 + 10.0 * energy.log10();
+        let blocks: Vec<f64> = self.subblocks
+            .windows(BLOCK_SUBDIVISIONS)
+            .map(|w| w.iter().sum::<f64>() / BLOCK_SUBDIVISIONS as f64)
+            .filter(|&energy| block_loudness(energy) > ABSOLUTE_GATE)
+            .collect();
+
+        let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+        let integrated = match blocks.is_empty() {
+            true => None,
+            false => {
+                let relative_gate = block_loudness(mean(&blocks)) + RELATIVE_GATE;
+                let gated: Vec<f64> = blocks.into_iter().filter(|&energy| block_loudness(energy) > relative_gate).collect();
+                Some(block_loudness(mean(&gated)))
+            }
+        };
+
+        Loudness {
+            integrated,
+            true_peak: 20.0 * self.peak.max(1e-10).log10()
+        }
+    }
+}
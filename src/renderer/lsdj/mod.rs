@@ -1,4 +1,5 @@
 mod save_file;
+mod song_file;
 mod end_detector;
 
 use anyhow::{Result, bail};
@@ -11,7 +12,8 @@ use std::time::Duration;
 use sameboy::{Gameboy, JoypadButton};
 use crate::renderer::SongPosition;
 
-pub use save_file::get_track_titles_from_save;
+pub use save_file::{get_track_titles_from_save, get_track_titles_from_sram};
+pub use song_file::{build_sram_from_songs, is_song_file};
 pub use end_detector::EndDetector;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
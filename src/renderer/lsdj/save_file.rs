@@ -1,25 +1,22 @@
 use anyhow::{Result, bail};
-use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::fs;
 use std::path::Path;
 
-pub fn get_track_titles_from_save<P: AsRef<Path>>(sav_path: P) -> Result<Vec<String>> {
-    let mut sav = BufReader::new(File::open(sav_path)?);
+pub const SRAM_SIZE: usize = 0x20000;
+pub const TITLES_OFFSET: usize = 0x8000;
+pub const VERSIONS_OFFSET: usize = 0x8100;
+pub const INIT_CHECK_OFFSET: usize = 0x813E;
+pub const ACTIVE_FILE_OFFSET: usize = 0x8140;
+pub const ALLOCATION_TABLE_OFFSET: usize = 0x8141;
+pub const BLOCKS_OFFSET: usize = 0x8200;
 
-    sav.seek(SeekFrom::Start(0x813E))?;
-    let mut jk = [0u8; 2];
-    sav.read_exact(&mut jk)?;
-    if &jk != b"jk" {
+pub fn get_track_titles_from_sram(sram: &[u8]) -> Result<Vec<String>> {
+    if sram.len() < BLOCKS_OFFSET || &sram[INIT_CHECK_OFFSET..INIT_CHECK_OFFSET + 2] != b"jk" {
         bail!("Invalid LSDj save file!");
     }
 
-    sav.seek(SeekFrom::Start(0x8000))?;
-
-    let mut titles = [0u8; 0x100];
-    sav.read_exact(&mut titles)?;
-
-    let mut versions = [0u8; 0x20];
-    sav.read_exact(&mut versions)?;
+    let titles = &sram[TITLES_OFFSET..TITLES_OFFSET + 0x100];
+    let versions = &sram[VERSIONS_OFFSET..VERSIONS_OFFSET + 0x20];
 
     let mut result: Vec<String> = Vec::new();
 
@@ -42,4 +39,9 @@ pub fn get_track_titles_from_save<P: AsRef<Path>>(sav_path: P) -> Result<Vec<Str
     }
 
     Ok(result)
-}
\ No newline at end of file
+}
+
+pub fn get_track_titles_from_save<P: AsRef<Path>>(sav_path: P) -> Result<Vec<String>> {
+    let sav = fs::read(sav_path)?;
+    get_track_titles_from_sram(&sav)
+}
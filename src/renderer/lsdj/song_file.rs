@@ -0,0 +1,225 @@
+use anyhow::{Result, Context, bail, ensure};
+use std::fs;
+use std::iter;
+use std::path::Path;
+use super::save_file::{SRAM_SIZE, TITLES_OFFSET, VERSIONS_OFFSET, INIT_CHECK_OFFSET, ACTIVE_FILE_OFFSET, ALLOCATION_TABLE_OFFSET, BLOCKS_OFFSET};
+
+const SONG_SIZE: usize = 0x8000;
+const BLOCK_SIZE: usize = 0x200;
+const BLOCK_COUNT: usize = 191;
+const FILE_COUNT: usize = 32;
+
+const RLE_CMD: u8 = 0xC0;
+const SPECIAL_CMD: u8 = 0xE0;
+const DEFAULT_WAVE_CMD: u8 = 0xF0;
+const DEFAULT_INSTRUMENT_CMD: u8 = 0xF1;
+const END_OF_FILE_CMD: u8 = 0xFF;
+
+const DEFAULT_WAVE: [u8; 16] = [
+    0x8E, 0xCD, 0xCC, 0xBB, 0xAA, 0xA9, 0x99, 0x88, 0x87, 0x76, 0x66, 0x55, 0x54, 0x43, 0x32, 0x31
+];
+const DEFAULT_INSTRUMENT: [u8; 16] = [
+    0xA8, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x03, 0x00, 0x00, 0xD0, 0x00, 0x00, 0x00, 0xF3, 0x00, 0x00
+];
+
+pub struct SongFile {
+    name: [u8; 8],
+    version: u8,
+    data: Vec<u8>
+}
+
+impl SongFile {
+    pub fn new(data: &[u8]) -> Result<Self> {
+        ensure!(data.len() > 9, "LSDj song file is too small!");
+
+        let mut name = [0u8; 8];
+        name.copy_from_slice(&data[0..8]);
+        let version = data[8];
+
+        let song_data = match decompress_blocks(&data[9..]) {
+            Ok(d) => d,
+            // Some tools store project files uncompressed
+            Err(_) if data.len() - 9 == SONG_SIZE => data[9..].to_vec(),
+            Err(e) => return Err(e)
+        };
+
+        Ok(Self {
+            name,
+            version,
+            data: song_data
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = fs::read(path)?;
+        Self::new(&data)
+    }
+}
+
+fn next_byte(data: &[u8], ptr: &mut usize) -> Result<u8> {
+    let result = *data.get(*ptr).context("Unexpected end of compressed song data!")?;
+    *ptr += 1;
+    Ok(result)
+}
+
+fn decompress_blocks(blocks: &[u8]) -> Result<Vec<u8>> {
+    let mut result: Vec<u8> = Vec::with_capacity(SONG_SIZE);
+    let mut block = 0usize;
+    let mut ptr = 0usize;
+
+    loop {
+        match next_byte(blocks, &mut ptr)? {
+            RLE_CMD => {
+                let value = next_byte(blocks, &mut ptr)?;
+                if value == RLE_CMD {
+                    result.push(RLE_CMD);
+                } else {
+                    let count = next_byte(blocks, &mut ptr)? as usize;
+                    result.extend(iter::repeat(value).take(count));
+                }
+            }
+            SPECIAL_CMD => {
+                match next_byte(blocks, &mut ptr)? {
+                    SPECIAL_CMD => result.push(SPECIAL_CMD),
+                    DEFAULT_WAVE_CMD => {
+                        let count = next_byte(blocks, &mut ptr)? as usize;
+                        for _ in 0..count {
+                            result.extend_from_slice(&DEFAULT_WAVE);
+                        }
+                    }
+                    DEFAULT_INSTRUMENT_CMD => {
+                        let count = next_byte(blocks, &mut ptr)? as usize;
+                        for _ in 0..count {
+                            result.extend_from_slice(&DEFAULT_INSTRUMENT);
+                        }
+                    }
+                    END_OF_FILE_CMD => break,
+                    _ => {
+                        // Song files store their blocks sequentially, so ignore the stored
+                        // block number (it refers to the save the song was exported from).
+                        block += 1;
+                        ptr = block * BLOCK_SIZE;
+                    }
+                }
+            }
+            value => result.push(value)
+        }
+
+        ensure!(result.len() <= SONG_SIZE, "Decompressed song data is too large!");
+    }
+
+    ensure!(result.len() == SONG_SIZE, "Decompressed song data is too small!");
+    Ok(result)
+}
+
+fn next_token(song: &[u8], i: usize) -> (Vec<u8>, usize) {
+    for (pattern, cmd) in [(&DEFAULT_INSTRUMENT, DEFAULT_INSTRUMENT_CMD), (&DEFAULT_WAVE, DEFAULT_WAVE_CMD)] {
+        let count = song[i..]
+            .chunks_exact(pattern.len())
+            .take_while(|&c| c == &pattern[..])
+            .take(0xFF)
+            .count();
+        if count > 0 {
+            return (vec![SPECIAL_CMD, cmd, count as u8], count * pattern.len());
+        }
+    }
+
+    let value = song[i];
+    let run = song[i..]
+        .iter()
+        .take_while(|&&b| b == value)
+        .take(0xFF)
+        .count();
+
+    match value {
+        // An RLE run of $C0 would be mistaken for an escaped $C0, so always escape it
+        RLE_CMD => (vec![RLE_CMD, RLE_CMD], 1),
+        _ if run >= 3 => (vec![RLE_CMD, value, run as u8], run),
+        SPECIAL_CMD => (vec![SPECIAL_CMD, SPECIAL_CMD], 1),
+        _ => (vec![value], 1)
+    }
+}
+
+fn compress(song: &[u8], first_block: usize) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::new();
+    let mut block: Vec<u8> = Vec::with_capacity(BLOCK_SIZE);
+    let mut next_block = first_block + 1;
+
+    let mut i = 0;
+    while i < song.len() {
+        let (token, consumed) = next_token(song, i);
+
+        // Always leave room for the block switch/end of file command
+        if block.len() + token.len() > BLOCK_SIZE - 2 {
+            block.push(SPECIAL_CMD);
+            block.push(next_block as u8);
+            block.resize(BLOCK_SIZE, 0);
+            result.append(&mut block);
+            next_block += 1;
+        }
+
+        block.extend(token);
+        i += consumed;
+    }
+
+    block.push(SPECIAL_CMD);
+    block.push(END_OF_FILE_CMD);
+    block.resize(BLOCK_SIZE, 0);
+    result.append(&mut block);
+
+    result
+}
+
+pub fn build_sram(songs: &[SongFile]) -> Result<Vec<u8>> {
+    ensure!(!songs.is_empty(), "No LSDj songs specified!");
+    ensure!(songs.len() <= FILE_COUNT, "Too many LSDj songs (maximum is {})!", FILE_COUNT);
+
+    let mut sram = vec![0u8; SRAM_SIZE];
+
+    // LSDj loads the working song on boot, so start off with the first song
+    sram[0..SONG_SIZE].copy_from_slice(&songs[0].data);
+    sram[INIT_CHECK_OFFSET..INIT_CHECK_OFFSET + 2].copy_from_slice(b"jk");
+    sram[ACTIVE_FILE_OFFSET] = 0;
+    sram[ALLOCATION_TABLE_OFFSET..BLOCKS_OFFSET].fill(0xFF);
+
+    let mut next_block = 1usize;
+    for (i, song) in songs.iter().enumerate() {
+        sram[(TITLES_OFFSET + 8 * i)..(TITLES_OFFSET + 8 * (i + 1))].copy_from_slice(&song.name);
+        sram[VERSIONS_OFFSET + i] = song.version;
+
+        let compressed = compress(&song.data, next_block);
+        let block_count = compressed.len() / BLOCK_SIZE;
+        if next_block - 1 + block_count > BLOCK_COUNT {
+            bail!("LSDj songs do not fit in a single save! (ran out of space at song {})", i + 1);
+        }
+
+        let offset = BLOCKS_OFFSET + (next_block - 1) * BLOCK_SIZE;
+        sram[offset..(offset + compressed.len())].copy_from_slice(&compressed);
+
+        let table_offset = ALLOCATION_TABLE_OFFSET + next_block - 1;
+        sram[table_offset..(table_offset + block_count)].fill(i as u8);
+
+        next_block += block_count;
+    }
+
+    Ok(sram)
+}
+
+pub fn build_sram_from_songs<P: AsRef<Path>>(song_paths: &[P]) -> Result<Vec<u8>> {
+    let songs = song_paths
+        .iter()
+        .map(|p| {
+            SongFile::open(p)
+                .with_context(|| format!("Failed to open LSDj song {}", p.as_ref().display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    build_sram(&songs)
+}
+
+pub fn is_song_file<P: AsRef<Path>>(path: P) -> bool {
+    match path.as_ref().extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("lsdsng") || ext.eq_ignore_ascii_case("lsdprj"),
+        None => false
+    }
+}
@@ -26,8 +26,48 @@ fn read_m3u_file<P: AsRef<Path>>(m3u_path: P) -> Result<String, String> {
     String::from_utf8(data).map_err(|e| e.to_string())
 }
 
-pub fn search<P: AsRef<Path>>(gbs_path: P) -> Result<HashMap<u8, (String, Option<Duration>)>, String> {
-    let mut result: HashMap<u8, (String, Option<Duration>)> = HashMap::new();
+#[derive(Clone, Default)]
+pub struct M3uEntry {
+    pub title: String,
+    pub duration: Option<Duration>,
+    pub intro: Option<Duration>,
+    pub loop_length: Option<Duration>,
+    pub fade: Option<Duration>,
+    pub loop_count: Option<u32>
+}
+
+impl M3uEntry {
+    /// Time to play before fading out: the intro plus the loop played `loop_count` times when the
+    /// playlist has all three, the track duration otherwise
+    pub fn play_length(&self) -> Option<Duration> {
+        match (self.intro, self.loop_length, self.loop_count) {
+            (Some(intro), Some(loop_length), Some(loop_count)) if loop_count > 0 && !loop_length.is_zero() => {
+                Some(intro + (loop_length * loop_count))
+            },
+            _ => self.duration
+        }
+    }
+}
+
+fn parse_time(s: &str) -> Option<Duration> {
+    let (s, fraction) = match s.trim().split_once('.') {
+        Some((s, fraction)) => (s, f64::from_str(&format!("0.{}", fraction)).ok()?),
+        None => (s.trim(), 0.0)
+    };
+    if s.is_empty() {
+        return None;
+    }
+
+    let mut seconds = 0u64;
+    for component in s.split(':') {
+        seconds = (seconds * 60) + u64::from_str(component).ok()?;
+    }
+
+    Some(Duration::from_secs_f64(seconds as f64 + fraction))
+}
+
+pub fn search<P: AsRef<Path>>(gbs_path: P) -> Result<HashMap<u8, M3uEntry>, String> {
+    let mut result: HashMap<u8, M3uEntry> = HashMap::new();
 
     let gbs_filename = gbs_path.as_ref().file_name().unwrap().to_str().unwrap().to_string();
 
@@ -89,18 +129,32 @@ pub fn search<P: AsRef<Path>>(gbs_path: P) -> Result<HashMap<u8, (String, Option
                 track_title.push_str("...");
             }
 
-            let duration_seconds = component_iter.next().unwrap_or("".to_string())
-                .split(':')
-                .fold(0u64, |acc, cur| {
-                    let duration_component = u64::from_str(cur).unwrap_or_default();
-                    (acc * 60) + duration_component
-                });
-            let duration = match duration_seconds {
-                0 => None,
-                _ => Some(Duration::from_secs(duration_seconds))
+            let duration = parse_time(&component_iter.next().unwrap_or_default()).filter(|d| !d.is_zero());
+
+            // The loop field is either a loop length, a loop start with a trailing '-', or just '-' to loop the whole track
+            let loop_field = component_iter.next().unwrap_or_default();
+            let (intro, loop_length) = match loop_field.trim().strip_suffix('-') {
+                Some(start) => {
+                    let intro = parse_time(start).unwrap_or_default();
+                    (Some(intro), duration.and_then(|d| d.checked_sub(intro)))
+                },
+                None => {
+                    let loop_length = parse_time(&loop_field);
+                    (duration.zip(loop_length).and_then(|(d, l)| d.checked_sub(l)), loop_length)
+                }
             };
 
-            result.insert(index, (track_title, duration));
+            let fade = parse_time(&component_iter.next().unwrap_or_default());
+            let loop_count = u32::from_str(component_iter.next().unwrap_or_default().trim()).ok();
+
+            result.insert(index, M3uEntry {
+                title: track_title,
+                duration,
+                intro,
+                loop_length,
+                fade,
+                loop_count
+            });
         }
     }
 
@@ -0,0 +1,76 @@
+use anyhow::{Result, anyhow};
+use crate::renderer::{gbs::Gbs, gbt, lsdj, m3u_searcher, uge, vgm};
+use crate::renderer::render_options::{RendererOptions, RenderInput};
+
+/// Container metadata (title, artist, album, track, ...) that can be read from the input itself.
+/// Per-track fields are left out when every track is rendered into one output.
+pub fn from_input(options: &RendererOptions) -> Result<Vec<(&'static str, String)>> {
+    let track_index = options.track_index;
+    let mut result: Vec<(&'static str, String)> = Vec::new();
+
+    match &options.input {
+        RenderInput::None => (),
+        RenderInput::GBS(gbs_path) => {
+            let gbs = Gbs::open(gbs_path).map_err(|e| anyhow!("Failed to read GBS! {}", e))?;
+            let album = gbs.title().unwrap_or_default();
+            // Unknown fields are usually filled with "<?>"
+            let known = |s: String| if s.trim() == "<?>" { String::new() } else { s };
+
+            if !options.album_mode {
+                let m3u_title = m3u_searcher::search(gbs_path)
+                    .ok()
+                    .and_then(|entries| entries.get(&track_index).map(|e| e.title.clone()));
+                if let Some(title) = m3u_title {
+                    result.push(("title", title));
+                }
+                result.push(("track", format!("{}/{}", track_index as u32 + 1, gbs.song_count())));
+            } else {
+                result.push(("title", known(album.clone())));
+            }
+            result.push(("album", known(album)));
+            result.push(("artist", known(gbs.artist().unwrap_or_default())));
+            result.push(("copyright", known(gbs.copyright().unwrap_or_default())));
+        },
+        RenderInput::LSDj(_, _) | RenderInput::LSDjSongs(_, _) | RenderInput::LSDj2x(_, _, _, _) | RenderInput::LSDjMulti(_) if !options.album_mode => {
+            let titles = match &options.input {
+                RenderInput::LSDj(_, sav_path) => lsdj::get_track_titles_from_save(sav_path)?,
+                RenderInput::LSDjSongs(_, song_paths) => lsdj::build_sram_from_songs(song_paths)
+                    .and_then(|sram| lsdj::get_track_titles_from_sram(&sram))?,
+                RenderInput::LSDj2x(_, sav_path, _, _) => lsdj::get_track_titles_from_save(sav_path)?,
+                RenderInput::LSDjMulti(files) => lsdj::get_track_titles_from_save(&files[0].1)?,
+                _ => unreachable!()
+            };
+            if let Some(title) = titles.get(track_index as usize) {
+                // Drop the save's version suffix (e.g. "SONG.3")
+                let name = title.rsplit_once('.').map(|(name, _)| name).unwrap_or(title);
+                result.push(("title", name.to_string()));
+                result.push(("track", format!("{}/{}", track_index as u32 + 1, titles.len())));
+            }
+        },
+        RenderInput::LSDj(_, _) | RenderInput::LSDjSongs(_, _) | RenderInput::LSDj2x(_, _, _, _) | RenderInput::LSDjMulti(_) => (),
+        RenderInput::VGM(vgm_path, _, _) => {
+            let vgm_s = vgm::Vgm::open(vgm_path)?;
+            if let Some(gd3) = vgm_s.gd3_metadata() {
+                result.extend(gd3.metadata(options.config.prefer_japanese_metadata));
+            }
+        },
+        RenderInput::UGE(uge_path) => {
+            let song = uge::Uge::open(uge_path)
+                .map_err(|e| anyhow!("Failed to read UGE! {}", e))?;
+            result.push(("title", song.name));
+            result.push(("artist", song.artist));
+            result.push(("comment", song.comment));
+        },
+        RenderInput::GBT(mod_path) => {
+            let module = gbt::Module::open(mod_path)
+                .map_err(|e| anyhow!("Failed to read MOD! {}", e))?;
+            result.push(("title", module.title));
+        }
+    }
+
+    Ok(result
+        .into_iter()
+        .map(|(key, value)| (key, value.trim().to_string()))
+        .filter(|(_, value)| !value.is_empty())
+        .collect())
+}
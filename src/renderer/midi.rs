@@ -0,0 +1,230 @@
+use anyhow::{Result, Context};
+use std::fs;
+use sameboy::{ApuChannel, ApuStateReceiver};
+use crate::renderer::stems;
+use crate::renderer::sample_clock::SampleClock;
+
+const TICKS_PER_QUARTER: u16 = 480;
+const MICROSECONDS_PER_QUARTER: u32 = 500_000;
+const TICKS_PER_SECOND: f64 = TICKS_PER_QUARTER as f64 * 1_000_000.0 / MICROSECONDS_PER_QUARTER as f64;
+// Default General MIDI pitch bend range, in semitones
+const BEND_RANGE: f64 = 2.0;
+
+const CC_PAN: u8 = 10;
+const CC_EXPRESSION: u8 = 11;
+
+#[derive(Default)]
+struct MidiTrack {
+    events: Vec<(u64, Vec<u8>)>,
+    last_tick: Option<u64>,
+
+    note: Option<u8>,
+    attack_volume: u8,
+    volume: u8,
+    expression: Option<u8>,
+    pan: Option<u8>,
+    bend: Option<u16>,
+    program: Option<u8>
+}
+
+impl MidiTrack {
+    fn push(&mut self, tick: u64, event: &[u8]) {
+        self.events.push((tick, event.to_vec()));
+    }
+
+    fn update(&mut self, tick: u64, midi_channel: u8, volume: u8, frequency: f64, timbre: usize, balance: f64) {
+        // Registers are only sampled once per tick, there's no point in emitting events any finer than that
+        if self.last_tick == Some(tick) {
+            return;
+        }
+        self.last_tick = Some(tick);
+
+        let volume = volume.min(15);
+        let pitch = match frequency.is_finite() && frequency > 0.0 {
+            true => Some(69.0 + 12.0 * (frequency / 440.0).log2()),
+            false => None
+        };
+
+        let pitch = match (pitch, volume) {
+            (Some(pitch), 1..) if (0.0..=127.0).contains(&pitch) => pitch,
+            _ => {
+                self.note_off(tick, midi_channel);
+                self.volume = 0;
+                return;
+            }
+        };
+
+        let program = (timbre % 128) as u8;
+        if self.program != Some(program) {
+            self.push(tick, &[0xC0 | midi_channel, program]);
+            self.program = Some(program);
+        }
+
+        let pan = (balance.clamp(0.0, 1.0) * 127.0).round() as u8;
+        if self.pan != Some(pan) {
+            self.push(tick, &[0xB0 | midi_channel, CC_PAN, pan]);
+            self.pan = Some(pan);
+        }
+
+        let retrigger = match self.note {
+            Some(note) => (pitch - note as f64).abs() > BEND_RANGE || volume > self.volume,
+            None => true
+        };
+        if retrigger {
+            self.note_off(tick, midi_channel);
+
+            let note = pitch.round() as u8;
+            let velocity = (volume as u32 * 127 / 15) as u8;
+            self.set_bend(tick, midi_channel, pitch - note as f64);
+            self.set_expression(tick, midi_channel, 127);
+            self.push(tick, &[0x90 | midi_channel, note, velocity]);
+            self.note = Some(note);
+            self.attack_volume = volume;
+        } else if let Some(note) = self.note {
+            // Envelope changes within a note are expressed relative to the velocity it started with
+            let expression = (127 * volume as u32 / self.attack_volume as u32).min(127) as u8;
+            self.set_expression(tick, midi_channel, expression);
+            self.set_bend(tick, midi_channel, pitch - note as f64);
+        }
+
+        self.volume = volume;
+    }
+
+    fn set_bend(&mut self, tick: u64, midi_channel: u8, semitones: f64) {
+        let bend = (8192.0 + (semitones / BEND_RANGE) * 8191.0).round().clamp(0.0, 16383.0) as u16;
+        if self.bend != Some(bend) {
+            self.push(tick, &[0xE0 | midi_channel, (bend & 0x7F) as u8, (bend >> 7) as u8]);
+            self.bend = Some(bend);
+        }
+    }
+
+    fn set_expression(&mut self, tick: u64, midi_channel: u8, expression: u8) {
+        if self.expression != Some(expression) {
+            self.push(tick, &[0xB0 | midi_channel, CC_EXPRESSION, expression]);
+            self.expression = Some(expression);
+        }
+    }
+
+    fn note_off(&mut self, tick: u64, midi_channel: u8) {
+        if let Some(note) = self.note.take() {
+            self.push(tick, &[0x80 | midi_channel, note, 0]);
+        }
+    }
+}
+
+/// Converts the APU state stream into a Standard MIDI File, with one track per channel and console
+pub struct MidiRecorder {
+    output_path: String,
+    sample_rate: u32,
+    clock: SampleClock,
+    tracks: Vec<MidiTrack>
+}
+
+impl MidiRecorder {
+    pub fn new(output_path: &str, sample_rate: u32) -> Self {
+        Self {
+            output_path: output_path.to_string(),
+            sample_rate,
+            clock: SampleClock::default(),
+            tracks: Vec::new()
+        }
+    }
+
+    /// Move every console forward in time without any APU activity (e.g. the gaps between album tracks)
+    pub fn skip_samples(&mut self, samples: u64) {
+        let tick = self.to_tick(self.clock.console_samples(0));
+        for (i, track) in self.tracks.iter_mut().enumerate() {
+            track.note_off(tick, midi_channel(i));
+            track.last_tick = None;
+        }
+
+        self.clock.skip(samples);
+    }
+
+    fn to_tick(&self, samples: u64) -> u64 {
+        (samples as f64 * TICKS_PER_SECOND / self.sample_rate as f64) as u64
+    }
+
+    pub fn write(&mut self) -> Result<()> {
+        let end_tick = self.to_tick(self.clock.samples());
+        for (i, track) in self.tracks.iter_mut().enumerate() {
+            track.note_off(end_tick, midi_channel(i));
+        }
+
+        let mut tempo_track = vec![0x00, 0xFF, 0x51, 0x03];
+        tempo_track.extend(&MICROSECONDS_PER_QUARTER.to_be_bytes()[1..]);
+        tempo_track.extend([0x00, 0xFF, 0x2F, 0x00]);
+
+        let mut data: Vec<u8> = Vec::new();
+        data.extend(b"MThd");
+        data.extend(6u32.to_be_bytes());
+        data.extend(1u16.to_be_bytes());
+        data.extend((self.tracks.len() as u16 + 1).to_be_bytes());
+        data.extend(TICKS_PER_QUARTER.to_be_bytes());
+        write_chunk(&mut data, &tempo_track);
+
+        let titles = stems::stem_titles(self.tracks.len().div_ceil(4));
+        for (track, title) in self.tracks.iter().zip(titles) {
+            let mut track_data: Vec<u8> = Vec::new();
+            track_data.extend([0x00, 0xFF, 0x03]);
+            write_vlq(&mut track_data, title.len() as u64);
+            track_data.extend(title.as_bytes());
+
+            let mut last_tick = 0;
+            for (tick, event) in track.events.iter() {
+                write_vlq(&mut track_data, tick - last_tick);
+                track_data.extend(event);
+                last_tick = *tick;
+            }
+            track_data.extend([0x00, 0xFF, 0x2F, 0x00]);
+
+            write_chunk(&mut data, &track_data);
+        }
+
+        fs::write(&self.output_path, data)
+            .with_context(|| format!("Failed to write MIDI file {}", self.output_path))
+    }
+}
+
+impl ApuStateReceiver for MidiRecorder {
+    fn receive(&mut self, id: usize, channel: ApuChannel, volume: u8, _amplitude: u8, frequency: f64, timbre: usize, balance: f64, _edge: bool) {
+        self.clock.receive(id, channel);
+
+        let index = (id * 4) + match channel {
+            ApuChannel::Pulse1 => 0,
+            ApuChannel::Pulse2 => 1,
+            ApuChannel::Wave => 2,
+            ApuChannel::Noise => 3
+        };
+        if self.tracks.len() <= index {
+            self.tracks.resize_with((index / 4 + 1) * 4, Default::default);
+        }
+
+        let tick = self.to_tick(self.clock.console_samples(id));
+        self.tracks[index].update(tick, midi_channel(index), volume, frequency, timbre, balance);
+    }
+}
+
+fn midi_channel(track: usize) -> u8 {
+    // MIDI channel 10 is reserved for percussion
+    match (track % 15) as u8 {
+        c if c >= 9 => c + 1,
+        c => c
+    }
+}
+
+fn write_chunk(data: &mut Vec<u8>, track: &[u8]) {
+    data.extend(b"MTrk");
+    data.extend((track.len() as u32).to_be_bytes());
+    data.extend(track);
+}
+
+fn write_vlq(data: &mut Vec<u8>, value: u64) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push(0x80 | (value & 0x7F) as u8);
+        value >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
@@ -2,19 +2,29 @@ pub mod render_options;
 pub mod lsdj;
 pub mod gbs;
 pub mod vgm;
+pub mod uge;
+pub mod gbt;
+pub mod tracker;
 pub mod m3u_searcher;
-
-use anyhow::{Result, anyhow, bail};
+pub mod loop_detector;
+pub mod album;
+pub mod stems;
+pub mod midi;
+pub mod sample_clock;
+pub mod metadata;
+pub mod loudness;
+
+use anyhow::{Result, anyhow, bail, ensure};
 use std::fmt::{Display, Formatter};
 use std::fs;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use ringbuf::{HeapRb, Rb, ring_buffer::RbBase};
 use render_options::{RendererOptions, RenderInput};
-use sameboy::{Gameboy, JoypadButton};
-use crate::renderer::render_options::StopCondition;
-use crate::video_builder;
-use crate::video_builder::VideoBuilder;
+use sameboy::{ApuChannel, ApuStateReceiver, Gameboy, JoypadButton, MemoryInterceptor, Model};
+use crate::renderer::render_options::{StartPosition, StopCondition};
+use crate::video_builder::OutputBuilder;
 use crate::visualizer::Visualizer;
 
 #[derive(Copy, Clone, Default, PartialEq)]
@@ -35,12 +45,21 @@ impl Display for SongPosition {
 
 pub struct Renderer {
     options: RendererOptions,
-    gb: Gameboy,
-    gb_2x: Gameboy,
+    consoles: Vec<Gameboy>,
     viz: Arc<Mutex<Visualizer>>,
+    midi: Option<Arc<Mutex<midi::MidiRecorder>>>,
+    vgm_recorder: Option<Arc<Mutex<vgm::recorder::VgmRecorder>>>,
+    apu_receiver: Arc<Mutex<dyn ApuStateReceiver>>,
     end_detector: Arc<Mutex<lsdj::EndDetector>>,
-    vgm_2x: bool,
-    vb: VideoBuilder,
+    loop_detector: loop_detector::LoopDetector,
+    vgm_player: Option<vgm::player::VgmPlayer>,
+    images: Vec<stems::ConsoleImage>,
+    stems: Option<stems::StemRecorder>,
+    loudness_meter: Option<loudness::LoudnessMeter>,
+    stop_condition: StopCondition,
+    fadeout_length: u64,
+    frame_rate: f64,
+    vb: OutputBuilder,
 
     cur_frame: u64,
     encode_start: Instant,
@@ -50,30 +69,143 @@ pub struct Renderer {
     loop_count: u64,
     loop_duration: Option<u64>,
     fadeout_timer: Option<u64>,
+    silent_frames: u64,
+    output_silent: bool,
+    /// Sample frames of audio output so far in the current track
+    audio_position: u64,
+    seek_frames: u64,
     expected_duration: Option<usize>
 }
 
 impl Renderer {
     pub fn new(options: RendererOptions) -> Result<Self> {
-        let gb = Gameboy::new(0, options.clone().model)?;
-        let gb_2x = Gameboy::new(1, options.clone().model)?;
+        let mut options = options;
+        for (key, value) in metadata::from_input(&options)? {
+            options.video_options.metadata.entry(key.to_string()).or_insert(value);
+        }
+        let console_count = match &options.input {
+            RenderInput::LSDj2x(_, _, _, _) => 2,
+            RenderInput::LSDjMulti(files) => files.len(),
+            RenderInput::VGM(vgm_path, _, _) => {
+                match vgm::Vgm::open(vgm_path)?.lr35902_clock() {
+                    Some((_, true)) => 2,
+                    _ => 1
+                }
+            },
+            _ => 1
+        };
+        ensure!(console_count > 0, "No LSDj ROM/SAV pairs given!");
+        ensure!(console_count <= 2 || !options.vgm_export, "VGM files can only hold up to two Game Boys!");
+        if matches!(&options.input, RenderInput::VGM(_, _, _)) && options.vgm_direct {
+            ensure!(
+                options.stem_format.is_none() && !options.stem_streams,
+                "Stems can't be exported with direct VGM playback!"
+            );
+        }
+        if matches!(options.start_at, Some(StartPosition::Row(_))) {
+            ensure!(
+                matches!(
+                    &options.input,
+                    RenderInput::LSDj(_, _) | RenderInput::LSDjSongs(_, _) | RenderInput::LSDj2x(_, _, _, _) | RenderInput::LSDjMulti(_)
+                ),
+                "Starting at a song row is only supported for LSDj!"
+            );
+        }
+        if options.stem_streams {
+            ensure!(!options.video_options.audio_only, "Audio stem streams can only be added to a video!");
+            options.video_options.audio_stem_titles = stems::stem_titles(console_count);
+        }
+
+        let mut consoles = new_consoles(console_count, options.model)?;
+        let clock_rate = consoles[0].clock_rate();
+        let frame_rate = consoles[0].usual_frame_rate();
+        options.adjust_time_base(clock_rate);
         let viz = Arc::new(Mutex::new(Visualizer::new(
-            8,
+            4 * console_count,
             options.video_options.resolution_in.0,
             options.video_options.resolution_in.1,
             options.video_options.sample_rate as u32,
-            options.config.clone().piano_roll
+            frame_rate,
+            options.config.clone().piano_roll,
+            options.config.clone().screen
         )));
-        let vb = VideoBuilder::new(options.video_options.clone())?;
+        let vb = match options.loudness_pass {
+            true => OutputBuilder::null(&options.video_options),
+            false => OutputBuilder::new(options.video_options.clone())?
+        };
+        let loudness_meter = match options.loudness_pass {
+            true => Some(loudness::LoudnessMeter::new(
+                options.video_options.sample_rate as u32,
+                options.video_options.audio_channels as usize
+            )),
+            false => None
+        };
         let end_detector = Arc::new(Mutex::new(lsdj::EndDetector::new()));
+        let stems = match options.stem_format.is_some() || options.stem_streams {
+            true => Some(stems::StemRecorder::new(
+                &options.video_options.output_path,
+                options.stem_format.as_deref(),
+                options.video_options.sample_rate,
+                &options.video_options.metadata,
+                options.config.audio.emulator_settings()
+            )),
+            false => None
+        };
+
+        let midi = match options.midi_export {
+            true => {
+                let midi_path = Path::new(&options.video_options.output_path).with_extension("mid");
+                Some(Arc::new(Mutex::new(midi::MidiRecorder::new(
+                    midi_path.to_str().unwrap(),
+                    options.video_options.sample_rate as u32
+                ))))
+            },
+            false => None
+        };
+        let vgm_recorder = match options.vgm_export {
+            true => {
+                let vgm_path = Path::new(&options.video_options.output_path).with_extension("vgm");
+                if let RenderInput::VGM(input_path, _, _) = &options.input {
+                    ensure!(Path::new(input_path) != vgm_path, "Recording a VGM would overwrite the input file!");
+                }
+                Some(Arc::new(Mutex::new(vgm::recorder::VgmRecorder::new(
+                    vgm_path.to_str().unwrap(),
+                    options.video_options.sample_rate as u32,
+                    clock_rate,
+                    console_count == 2
+                ))))
+            },
+            false => None
+        };
+
+        let mut apu_receivers: Vec<Arc<Mutex<dyn ApuStateReceiver>>> = vec![viz.clone()];
+        if let Some(midi) = &midi {
+            apu_receivers.push(midi.clone());
+        }
+        if let Some(vgm_recorder) = &vgm_recorder {
+            apu_receivers.push(vgm_recorder.clone());
+        }
+        let apu_receiver: Arc<Mutex<dyn ApuStateReceiver>> = match apu_receivers.len() {
+            1 => viz.clone(),
+            _ => Arc::new(Mutex::new(ApuStateSplitter(apu_receivers)))
+        };
 
         Ok(Self {
             options: options.clone(),
-            gb,
-            gb_2x,
+            consoles,
             viz,
+            midi,
+            vgm_recorder,
+            apu_receiver,
             end_detector,
-            vgm_2x: false,
+            loop_detector: loop_detector::LoopDetector::new(frame_rate),
+            vgm_player: None,
+            images: Vec::new(),
+            stems,
+            loudness_meter,
+            stop_condition: options.stop_condition,
+            fadeout_length: options.fadeout_length,
+            frame_rate,
             vb,
             cur_frame: 0,
             encode_start: Instant::now(),
@@ -83,48 +215,125 @@ impl Renderer {
             loop_count: 0,
             loop_duration: None,
             fadeout_timer: None,
+            silent_frames: 0,
+            output_silent: true,
+            audio_position: 0,
+            seek_frames: 0,
             expected_duration: None
         })
     }
 
-    pub fn is_2x(&self) -> bool {
-        match &self.options.input {
-            RenderInput::LSDj2x(_, _, _, _) => true,
-            RenderInput::VGM(_, _, _) => self.vgm_2x,
-            _ => false
-        }
+    pub fn console_count(&self) -> usize {
+        self.consoles.len()
+    }
+
+    /// Whether the consoles are connected with a link cable, which only works for a pair
+    fn is_linked(&self) -> bool {
+        matches!(&self.options.input, RenderInput::LSDj2x(_, _, _, _))
     }
 
     pub fn start_encoding(&mut self) -> Result<()> {
-        self.gb.set_sample_rate(self.options.video_options.sample_rate as usize);
-        self.gb.emulate_joypad_bouncing(false);
-        self.gb.allow_illegal_inputs(true);
-        self.gb.set_rendering_disabled(true);
+        self.load_track()?;
+        self.vb.start_encoding()?;
+
+        Ok(())
+    }
+
+    /// Reset the emulator and start playing another track into the same output
+    pub fn start_next_track(&mut self, track_index: u8) -> Result<()> {
+        self.options.track_index = track_index;
+        self.consoles = new_consoles(self.consoles.len(), self.options.model)?;
 
-        self.vgm_2x = false;
+        self.load_track()
+    }
+
+    /// Encode silent frames of the visualizer between tracks
+    pub fn render_gap(&mut self, frames: u64) -> Result<()> {
+        let frame_size = self.vb.audio_frame_size();
+        let time_base = self.options.video_options.video_time_base;
+        let samples_per_frame = self.options.video_options.sample_rate as f64 * time_base.numerator() as f64 / time_base.denominator() as f64;
+        let silence = vec![0i16; frame_size * self.options.video_options.audio_channels as usize];
+
+        let mut pending_samples = 0.0;
+        for _ in 0..frames {
+            if self.vb.has_video() {
+                let mut viz = self.viz.lock().unwrap();
+                viz.draw();
+                self.vb.push_video_data(viz.get_canvas_buffer())?;
+            }
+
+            pending_samples += samples_per_frame;
+            while pending_samples >= frame_size as f64 {
+                self.vb.push_audio_data(&silence)?;
+                if let Some(stems) = &mut self.stems {
+                    stems.push_silence(&silence)?;
+                }
+                for i in 0..self.options.video_options.audio_stem_titles.len() {
+                    self.vb.push_stem_audio_data(i, &silence)?;
+                }
+                pending_samples -= frame_size as f64;
+                if let Some(midi) = &self.midi {
+                    midi.lock().unwrap().skip_samples(frame_size as u64);
+                }
+                if let Some(vgm_recorder) = &self.vgm_recorder {
+                    vgm_recorder.lock().unwrap().skip_samples(frame_size as u64);
+                }
+            }
+
+            self.vb.step_encoding()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn add_chapter(&mut self, title: &str, start: Duration, end: Duration) -> Result<()> {
+        self.vb.add_chapter(title, start, end)
+    }
+
+    fn load_track(&mut self) -> Result<()> {
+        (self.stop_condition, self.fadeout_length) = self.options.resolve_stop_condition(self.frame_rate)?;
+
+        self.configure_consoles();
+
+        self.vgm_player = None;
+        self.seek_frames = 0;
+        self.images.clear();
+
+        if let Some(vgm_recorder) = &self.vgm_recorder {
+            for gb in self.consoles.iter_mut() {
+                gb.set_memory_interceptor(Some(vgm_recorder.clone()));
+            }
+        }
 
         match &self.options.input {
             RenderInput::None => bail!("No input specified."),
             RenderInput::GBS(gbs_path) => {
                 let gbs = fs::read(gbs_path)
                     .map_err(|e| anyhow!("Failed to read GBS! {}", e))?;
-                self.gb.load_gbs(&gbs)
+                self.consoles[0].load_gbs(&gbs)
                     .map_err(|e| anyhow!("Failed to load GBS! {}", e))?;
-                self.gb.gbs_change_track(self.options.track_index);
+                self.consoles[0].gbs_change_track(self.options.track_index);
+                self.images.push(stems::ConsoleImage::Gbs(gbs));
             },
-            RenderInput::LSDj(rom_path, sav_path) => {
+            RenderInput::LSDj(rom_path, _) | RenderInput::LSDjSongs(rom_path, _) => {
                 let rom = fs::read(rom_path)
                     .map_err(|e| anyhow!("Failed to read LSDj ROM! {}", e))?;
-                self.gb.load_rom(&rom);
-
-                let sav = fs::read(sav_path)
-                    .map_err(|e| anyhow!("Failed to read LSDj SAV! {}", e))?;
-                self.gb.load_sram(&sav);
+                self.consoles[0].load_rom(&rom);
+                self.images.push(stems::ConsoleImage::Rom(rom));
+
+                let sav = match &self.options.input {
+                    RenderInput::LSDj(_, sav_path) => fs::read(sav_path)
+                        .map_err(|e| anyhow!("Failed to read LSDj SAV! {}", e))?,
+                    RenderInput::LSDjSongs(_, song_paths) => lsdj::build_sram_from_songs(song_paths)
+                        .map_err(|e| anyhow!("Failed to build LSDj SAV from songs! {}", e))?,
+                    _ => unreachable!()
+                };
+                self.consoles[0].load_sram(&sav);
 
-                println!("{} {}", self.gb.game_title().unwrap_or("<error>".to_string()), self.options.track_index);
+                println!("{} {}", self.consoles[0].game_title().unwrap_or("<error>".to_string()), self.options.track_index);
 
-                while !self.gb.boot_rom_finished() {
-                    self.gb.run();
+                while !self.consoles[0].boot_rom_finished() {
+                    self.consoles[0].run();
                 }
 
                 let sync_role = if self.options.auto_lsdj_sync {
@@ -133,118 +342,169 @@ impl Renderer {
                     lsdj::SyncRole::Ignore
                 };
 
-                self.gb.joypad_macro_press(&[], Some(Duration::from_secs(5)));
-                lsdj::select_track_joypad_macro(&mut self.gb, self.options.track_index, sync_role);
+                self.consoles[0].joypad_macro_press(&[], Some(Duration::from_secs(5)));
+                lsdj::select_track_joypad_macro(&mut self.consoles[0], self.options.track_index, sync_role);
 
-                self.gb.set_memory_interceptor(Some(self.end_detector.clone()));
+                self.attach_end_detector();
             },
-            RenderInput::LSDj2x(rom_path, sav_path, rom_path_2x, sav_path_2x) => {
-                self.gb_2x.set_sample_rate(self.options.video_options.sample_rate as usize);
-                self.gb_2x.emulate_joypad_bouncing(false);
-                self.gb_2x.allow_illegal_inputs(true);
-                self.gb_2x.set_rendering_disabled(true);
-
-                let rom = fs::read(rom_path)
-                    .map_err(|e| anyhow!("Failed to read LSDj ROM! {}", e))?;
-                self.gb.load_rom(&rom);
-
-                let sav = fs::read(sav_path)
-                    .map_err(|e| anyhow!("Failed to read LSDj SAV! {}", e))?;
-                self.gb.load_sram(&sav);
-
-                let rom_2x = fs::read(rom_path_2x)
-                    .map_err(|e| anyhow!("Failed to read LSDj ROM! {}", e))?;
-                self.gb_2x.load_rom(&rom_2x);
-
-                let sav_2x = fs::read(sav_path_2x)
-                    .map_err(|e| anyhow!("Failed to read LSDj SAV! {}", e))?;
-                self.gb_2x.load_sram(&sav_2x);
-
-                println!("(1) {} {}", self.gb.game_title().unwrap_or("<error>".to_string()), self.options.track_index);
-                println!("(2) {} {}", self.gb_2x.game_title().unwrap_or("<error>".to_string()), self.options.track_index_2x);
+            RenderInput::LSDj2x(_, _, _, _) | RenderInput::LSDjMulti(_) => {
+                let files = match &self.options.input {
+                    RenderInput::LSDj2x(rom_path, sav_path, rom_path_2x, sav_path_2x) => vec![
+                        (rom_path.clone(), sav_path.clone()),
+                        (rom_path_2x.clone(), sav_path_2x.clone())
+                    ],
+                    RenderInput::LSDjMulti(files) => files.clone(),
+                    _ => unreachable!()
+                };
 
-                while !self.gb.boot_rom_finished() {
-                    self.gb.run();
+                for (console, (rom_path, sav_path)) in files.iter().enumerate() {
+                    let rom = fs::read(rom_path)
+                        .map_err(|e| anyhow!("Failed to read LSDj ROM! {}", e))?;
+                    self.consoles[console].load_rom(&rom);
+                    self.images.push(stems::ConsoleImage::Rom(rom));
+
+                    let sav = fs::read(sav_path)
+                        .map_err(|e| anyhow!("Failed to read LSDj SAV! {}", e))?;
+                    self.consoles[console].load_sram(&sav);
+
+                    println!(
+                        "({}) {} {}",
+                        console + 1,
+                        self.consoles[console].game_title().unwrap_or("<error>".to_string()),
+                        self.options.console_track_index(console)
+                    );
                 }
-                while !self.gb_2x.boot_rom_finished() {
-                    self.gb_2x.run();
+
+                for gb in self.consoles.iter_mut() {
+                    while !gb.boot_rom_finished() {
+                        gb.run();
+                    }
                 }
 
-                let (sync_role, sync_role_2x) = if self.options.auto_lsdj_sync {
-                    (lsdj::SyncRole::Primary, lsdj::SyncRole::Secondary)
-                } else {
-                    (lsdj::SyncRole::Ignore, lsdj::SyncRole::Ignore)
-                };
+                let linked = self.is_linked();
+                for console in 0..self.consoles.len() {
+                    let sync_role = match (self.options.auto_lsdj_sync, linked, console) {
+                        (false, _, _) => lsdj::SyncRole::Ignore,
+                        (true, true, 0) => lsdj::SyncRole::Primary,
+                        (true, true, _) => lsdj::SyncRole::Secondary,
+                        // Unlinked consoles are kept together by starting on the same frame instead
+                        (true, false, _) => lsdj::SyncRole::NoSync
+                    };
+                    let track_index = self.options.console_track_index(console);
 
-                self.gb.joypad_macro_press(&[], Some(Duration::from_secs(5)));
-                lsdj::select_track_joypad_macro(&mut self.gb, self.options.track_index, sync_role);
-                self.gb_2x.joypad_macro_press(&[], Some(Duration::from_secs(5)));
-                lsdj::select_track_joypad_macro(&mut self.gb_2x, self.options.track_index_2x, sync_role_2x);
+                    self.consoles[console].joypad_macro_press(&[], Some(Duration::from_secs(5)));
+                    lsdj::select_track_joypad_macro(&mut self.consoles[console], track_index, sync_role);
+                }
 
-                self.gb.set_memory_interceptor(Some(self.end_detector.clone()));
+                self.attach_end_detector();
             }
             RenderInput::VGM(vgm_path, engine_rate, tma_offset) => {
                 let vgm_data = fs::read(vgm_path)
                     .map_err(|e| anyhow!("Failed to read VGM! {}", e))?;
 
                 let mut vgm_s = vgm::Vgm::new(&vgm_data)?;
-                self.vgm_2x = vgm_s.lr35902_clock().map(|(_c, v)| v).unwrap_or_default();
 
-                let gbs = vgm::converter::vgm_to_gbs(&mut vgm_s, false, *engine_rate, *tma_offset)?;
-                self.gb.load_gbs(&gbs)
+                let gbs = match self.options.vgm_direct {
+                    true => {
+                        self.vgm_player = Some(vgm::player::VgmPlayer::new(&vgm_s)?);
+                        match vgm_s.gd3_metadata() {
+                            Some(gd3) => vgm::converter::idle_gbs(&gd3.title, &gd3.author, &gd3.game),
+                            None => vgm::converter::idle_gbs("<?>", "<?>", "<?>")
+                        }
+                    },
+                    false => vgm::converter::vgm_to_gbs(&mut vgm_s, false, *engine_rate, *tma_offset)?
+                };
+                self.consoles[0].load_gbs(&gbs)
                     .map_err(|e| anyhow!("Failed to convert VGM to valid GBS! {}", e))?;
+                self.images.push(stems::ConsoleImage::Gbs(gbs.clone()));
 
-                if self.is_2x() {
-                    self.gb_2x.set_sample_rate(self.options.video_options.sample_rate as usize);
-                    self.gb_2x.emulate_joypad_bouncing(false);
-                    self.gb_2x.allow_illegal_inputs(true);
-                    self.gb_2x.set_rendering_disabled(true);
-
-                    let gbs_2x = vgm::converter::vgm_to_gbs(&mut vgm_s, true, *engine_rate, *tma_offset)?;
-                    self.gb_2x.load_gbs(&gbs_2x)
+                if self.consoles.len() > 1 {
+                    let gbs_2x = match self.options.vgm_direct {
+                        true => gbs,
+                        false => vgm::converter::vgm_to_gbs(&mut vgm_s, true, *engine_rate, *tma_offset)?
+                    };
+                    self.consoles[1].load_gbs(&gbs_2x)
                         .map_err(|e| anyhow!("Failed to convert VGM to valid GBS! {}", e))?;
+                    self.images.push(stems::ConsoleImage::Gbs(gbs_2x));
                 }
             }
+            RenderInput::UGE(uge_path) => {
+                let uge_data = fs::read(uge_path)
+                    .map_err(|e| anyhow!("Failed to read UGE! {}", e))?;
+
+                let song = uge::Uge::new(&uge_data)?;
+                let gbs = uge::uge_to_gbs(&song)?;
+                self.consoles[0].load_gbs(&gbs)
+                    .map_err(|e| anyhow!("Failed to convert UGE to valid GBS! {}", e))?;
+                self.images.push(stems::ConsoleImage::Gbs(gbs));
+            }
+            RenderInput::GBT(mod_path) => {
+                let mod_data = fs::read(mod_path)
+                    .map_err(|e| anyhow!("Failed to read MOD! {}", e))?;
+
+                let module = gbt::Module::new(&mod_data)?;
+                let gbs = gbt::gbt_to_gbs(&module)?;
+                self.consoles[0].load_gbs(&gbs)
+                    .map_err(|e| anyhow!("Failed to convert MOD to valid GBS! {}", e))?;
+                self.images.push(stems::ConsoleImage::Gbs(gbs));
+            }
         }
 
-        self.gb.joypad_release_all();
-        self.gb.set_apu_receiver(Some(self.viz.clone()));
-        // Clear the sample buffer to get rid of boot ROM ding and LSDj selection frame silence
-        let _ = self.gb.get_audio_samples(None).unwrap();
+        if let Some(vgm_recorder) = &self.vgm_recorder {
+            let title = self.options.video_options.metadata.get("title").cloned().unwrap_or_default();
+            let (game, author, notes) = match &self.options.input {
+                RenderInput::GBS(gbs_path) => {
+                    let gbs = gbs::Gbs::open(gbs_path).map_err(|e| anyhow!("Failed to read GBS! {}", e))?;
+                    (gbs.title().unwrap_or_default(), gbs.artist().unwrap_or_default(), gbs.copyright().unwrap_or_default())
+                },
+                _ => (String::new(), self.options.video_options.metadata.get("artist").cloned().unwrap_or_default(), String::new())
+            };
+
+            let mut vgm_recorder = vgm_recorder.lock().unwrap();
+            vgm_recorder.set_gd3(&title, &game, &author, &notes);
+            vgm_recorder.start_track();
+        }
 
-        if self.is_2x() {
-            self.gb_2x.joypad_release_all();
-            self.gb_2x.set_apu_receiver(Some(self.viz.clone()));
-            let _ = self.gb_2x.get_audio_samples(None).unwrap();
+        for gb in self.consoles.iter_mut() {
+            gb.joypad_release_all();
+            // Clear the sample buffer to get rid of boot ROM ding and LSDj selection frame silence
+            let _ = gb.get_audio_samples(None).unwrap();
+        }
 
-            if matches!(&self.options.input, RenderInput::LSDj2x(_, _, _, _)) {
-                self.gb.run_frame();
-                self.gb_2x.run_frame();
-                self.gb.connect_console(&mut self.gb_2x);
+        if self.is_linked() {
+            if let [gb, gb_2x] = self.consoles.as_mut_slice() {
+                gb.run_frame();
+                gb_2x.run_frame();
+                gb.connect_console(gb_2x);
             }
         }
 
+        if let Some(start_at) = self.options.start_at {
+            self.seek(start_at)?;
+        }
+
+        for gb in self.consoles.iter_mut() {
+            gb.set_apu_receiver(Some(self.apu_receiver.clone()));
+        }
+
         {
-            let mut viz = self.viz.lock().unwrap();
+            let viz = self.viz.lock().unwrap();
 
-            let all_channels_hidden = (0..8).all(|i| viz.settings_manager().settings(i).unwrap().hidden());
+            let all_channels_hidden = (0..4 * self.consoles.len()).all(|i| viz.settings_manager().settings(i).unwrap().hidden());
             if all_channels_hidden {
                 bail!("At least one channel must be visible!");
             }
+        }
 
-            if !self.is_2x() {
-                for channel in 4..8 {
-                    viz.settings_manager_mut()
-                        .settings_mut(channel)
-                        .unwrap()
-                        .set_hidden(true);
-                }
-            }
+        if let Some(stems) = &mut self.stems {
+            let linked = self.is_linked();
+            stems.attach(&mut self.consoles, &self.images, linked)?;
         }
 
         self.end_detector.lock().unwrap().reset();
+        self.loop_detector.reset();
 
-        self.vb.start_encoding()?;
+        self.cur_frame = 0;
         self.encode_start = Instant::now();
         self.frame_timestamp = 0.0;
         self.frame_times.clear();
@@ -252,66 +512,222 @@ impl Renderer {
         self.loop_count = 0;
         self.loop_duration = None;
         self.fadeout_timer = None;
+        self.silent_frames = 0;
+        self.output_silent = true;
+        self.audio_position = 0;
         self.expected_duration = None;
 
         Ok(())
     }
 
-    pub fn step(&mut self) -> Result<bool> {
-        if self.is_2x() {
-            self.gb.run_frame_sync(&mut self.gb_2x);
+    fn show_screen(&self) -> bool {
+        self.options.config.screen.enabled && !self.options.video_options.audio_only && !self.options.loudness_pass
+    }
 
-            if self.frame_timestamp < 0.5 && matches!(&self.options.input, RenderInput::LSDj2x(_, _, _, _)) {
-                self.gb.set_joypad_button(JoypadButton::Start, true);
-            } else {
-                self.gb.joypad_release_all();
+    /// Only render the LCD when it's shown in the visualizer
+    fn configure_consoles(&mut self) {
+        let show_screen = self.show_screen();
+        let screen_config = self.options.config.screen.clone();
+        let (highpass_mode, interference) = self.options.config.audio.emulator_settings();
+
+        for gb in self.consoles.iter_mut() {
+            gb.set_sample_rate(self.options.video_options.sample_rate as usize);
+            gb.emulate_joypad_bouncing(false);
+            gb.allow_illegal_inputs(true);
+            gb.set_highpass_filter_mode(highpass_mode);
+            gb.set_interference_volume(interference);
+
+            gb.set_rendering_disabled(!show_screen);
+            if show_screen {
+                gb.set_color_correction_mode(screen_config.color_correction);
+                gb.set_palette(screen_config.dmg_palette);
             }
-            self.gb_2x.joypad_release_all();
-        } else {
-            self.gb.run_frame();
+        }
+    }
+
+    fn attach_end_detector(&mut self) {
+        let end_detector: Arc<Mutex<dyn MemoryInterceptor>> = self.end_detector.clone();
+        self.consoles[0].set_memory_interceptor(Some(match &self.vgm_recorder {
+            Some(vgm_recorder) => Arc::new(Mutex::new(MemoryInterceptorSplitter(vec![end_detector, vgm_recorder.clone()]))),
+            None => end_detector
+        }));
+    }
+
+    fn is_lsdj(&self) -> bool {
+        matches!(
+            &self.options.input,
+            RenderInput::LSDj(_, _) | RenderInput::LSDjSongs(_, _) | RenderInput::LSDj2x(_, _, _, _) | RenderInput::LSDjMulti(_)
+        )
+    }
+
+    /// Run the main console(s) until the start position is reached, without visualizing or encoding anything
+    fn seek(&mut self, start_at: StartPosition) -> Result<()> {
+        let start_at = match start_at {
+            StartPosition::Time(seconds) => StartPosition::Frames((seconds * self.frame_rate).round() as u64),
+            _ => start_at
+        };
+        let mut frames = 0;
+        let mut last_row: Option<u8> = None;
+
+        loop {
+            match start_at {
+                StartPosition::Time(_) => unreachable!(),
+                StartPosition::Frames(start_frames) => {
+                    if frames >= start_frames {
+                        break;
+                    }
+                },
+                StartPosition::Row(row) => match self.song_position() {
+                    Some(position) => {
+                        ensure!(!position.end, "The song ended before reaching row {:02X}!", row);
+                        ensure!(last_row.map_or(true, |last_row| position.row >= last_row), "The song looped before reaching row {:02X}!", row);
+                        if position.row >= row {
+                            break;
+                        }
+                        last_row = Some(position.row);
+                    },
+                    None => ensure!((frames as f64) < 10.0 * self.frame_rate, "Couldn't read the LSDj song position!")
+                }
+            }
+
+            let start_pressed = self.is_lsdj() && (frames as f64) < self.frame_rate / 2.0;
+            self.run_frame(start_pressed);
+
+            for gb in self.consoles.iter_mut() {
+                let _ = gb.get_audio_samples(None);
+            }
+            frames += 1;
+        }
+
+        self.seek_frames = frames;
+
+        Ok(())
+    }
 
-            if self.frame_timestamp < 0.5 && matches!(&self.options.input, RenderInput::LSDj(_, _)) {
-                self.gb.set_joypad_button(JoypadButton::Start, true);
+    fn run_frame(&mut self, start_pressed: bool) {
+        if let Some(vgm_player) = &mut self.vgm_player {
+            let (gb, others) = self.consoles.split_first_mut().unwrap();
+            vgm_player.run_frame(gb, others.first_mut());
+            return;
+        }
+
+        match self.consoles.as_mut_slice() {
+            [gb] => { gb.run_frame(); },
+            consoles => Gameboy::run_frames_sync(consoles)
+        }
+
+        let start_buttons = self.start_buttons(start_pressed);
+        for (gb, pressed) in self.consoles.iter_mut().zip(start_buttons) {
+            if pressed {
+                gb.set_joypad_button(JoypadButton::Start, true);
             } else {
-                self.gb.joypad_release_all();
+                gb.joypad_release_all();
             }
         }
+    }
 
-        {
+    /// Which consoles get Start pressed this frame. A linked secondary console is started by the primary.
+    fn start_buttons(&self, start_pressed: bool) -> Vec<bool> {
+        let linked = self.is_linked();
+        (0..self.consoles.len())
+            .map(|i| start_pressed && (i == 0 || !linked))
+            .collect()
+    }
+
+    /// Fade gain at a fractional frame position in the current track, combining the fade-in and fadeout
+    fn fade_gain(&self, position: f64) -> f64 {
+        let curve = self.options.fade_curve;
+        let fade_gain = |position: f64, length: u64| match length {
+            0 => 1.0,
+            _ => curve.gain(position / length as f64)
+        };
+
+        let fadein = fade_gain(position, self.options.fadein_length);
+        // The fadeout timer counts down by one every frame from the start of the current one
+        let fadeout = match self.fadeout_timer {
+            Some(t) => fade_gain(t as f64 - (position - self.cur_frame as f64), self.fadeout_length),
+            None => 1.0
+        };
+
+        fadein * fadeout
+    }
+
+    /// Gain for each sample of the next audio chunk, from the sample's position in the track
+    fn chunk_gains(&self, frame_size: usize) -> Vec<f64> {
+        let output_gain = 10f64.powf(self.options.gain_db / 20.0);
+        let samples_per_frame = self.options.video_options.sample_rate as f64 / self.frame_rate;
+        (0..frame_size as u64)
+            .map(|i| self.fade_gain((self.audio_position + i) as f64 / samples_per_frame) * output_gain)
+            .collect()
+    }
+
+    pub fn step(&mut self) -> Result<bool> {
+        // LSDj already got its Start press while seeking
+        let start_pressed = self.seek_frames == 0 && self.frame_timestamp < 0.5 && self.is_lsdj();
+        self.run_frame(start_pressed);
+
+        let start_buttons = self.start_buttons(start_pressed);
+        if let Some(stems) = &mut self.stems {
+            stems.run_frame(&start_buttons);
+        }
+
+        if self.vb.has_video() {
+            let brightness = self.fade_gain(self.cur_frame as f64);
             let mut viz = self.viz.lock().unwrap();
+            if self.options.fade_video {
+                viz.set_brightness(brightness as f32);
+            }
+            if self.show_screen() {
+                let screens: Vec<(usize, usize, Vec<u32>)> = self.consoles
+                    .iter_mut()
+                    .map(|gb| {
+                        let (width, height) = gb.screen_size();
+                        (width, height, gb.screen_buffer())
+                    })
+                    .collect();
+                viz.set_screens(&screens);
+            }
             viz.draw();
             self.vb.push_video_data(viz.get_canvas_buffer())?;
         }
 
-        if self.is_2x() {
-            if let Some(audio) = self.gb.get_audio_samples(Some(self.vb.audio_frame_size())) {
-                if let Some(audio_2x) = self.gb_2x.get_audio_samples(Some(self.vb.audio_frame_size())) {
-                    let adjusted_audio: Vec<i16> = match self.fadeout_timer {
-                        Some(t) => {
-                            let volume_divisor = (self.options.fadeout_length as f64 / t as f64) as i16;
-                            std::iter::zip(audio, audio_2x)
-                                .map(|(s, s_2x)| s.saturating_add(s_2x) / (2 * volume_divisor))
-                                .collect()
-                        },
-                        None => {
-                            std::iter::zip(audio, audio_2x)
-                                .map(|(s, s_2x)| s.saturating_add(s_2x) / 2)
-                                .collect()
-                        }
-                    };
-                    self.vb.push_audio_data(video_builder::as_u8_slice(&adjusted_audio))?;
+        let frame_size = self.vb.audio_frame_size();
+        let gains = self.chunk_gains(frame_size);
+        let audio_channels = self.options.video_options.audio_channels as usize;
+        let console_audio: Option<Vec<Vec<i16>>> = self.consoles
+            .iter_mut()
+            .map(|gb| gb.get_audio_samples(Some(frame_size)))
+            .collect();
+        let mix_divisor = self.consoles.len() as f64;
+        let mixed_audio: Option<Vec<f32>> = console_audio.map(|console_audio| {
+            let mut mixed = vec![0.0f32; console_audio[0].len()];
+            for audio in console_audio.iter() {
+                for (m, &s) in mixed.iter_mut().zip(audio) {
+                    *m += s as f32;
                 }
             }
-        } else {
-            if let Some(audio) = self.gb.get_audio_samples(Some(self.vb.audio_frame_size())) {
-                let adjusted_audio = match self.fadeout_timer {
-                    Some(t) => {
-                        let volume_divisor = (self.options.fadeout_length as f64 / t as f64) as i16;
-                        audio.iter().map(|s| s / volume_divisor).collect()
-                    },
-                    None => audio
-                };
-                self.vb.push_audio_data(video_builder::as_u8_slice(&adjusted_audio))?;
+            mixed.iter().map(|&s| s / mix_divisor as f32).collect()
+        });
+        if let Some(audio) = mixed_audio {
+            self.update_silence(audio.iter().fold(0.0, |peak: f32, s| peak.max(s.abs())) as u16);
+
+            let adjusted_audio = apply_gains(&audio, &gains, audio_channels);
+            if let Some(loudness_meter) = &mut self.loudness_meter {
+                loudness_meter.push(&adjusted_audio);
+            }
+            self.vb.push_audio_data(&adjusted_audio)?;
+            self.audio_position += (audio.len() / audio_channels.max(1)) as u64;
+        }
+
+        if let Some(stems) = &mut self.stems {
+            let stem_gains: Vec<f64> = gains.iter().map(|g| g / mix_divisor).collect();
+            let stem_audio = stems.push_audio(frame_size, &stem_gains, audio_channels)?;
+            if self.options.stem_streams {
+                for (i, audio) in stem_audio.iter().enumerate() {
+                    if let Some(audio) = audio {
+                        self.vb.push_stem_audio_data(i, audio)?;
+                    }
+                }
             }
         }
 
@@ -344,15 +760,66 @@ impl Renderer {
             self.last_position = current_position;
         }
 
+        if let Some(vgm_player) = &self.vgm_player {
+            if vgm_player.loop_count() > self.loop_count {
+                self.loop_count = vgm_player.loop_count();
+                if self.loop_duration.is_none() {
+                    self.loop_duration = vgm_player.loop_frames(self.frame_rate);
+                }
+            }
+            if vgm_player.ended() {
+                self.last_position.end = true;
+            }
+        }
+
+        if matches!(&self.options.input, RenderInput::GBS(_)) {
+            if self.loop_detector.update(&mut self.consoles[0], self.cur_frame, self.output_silent) {
+                self.loop_count += 1;
+                if self.loop_duration.is_none() {
+                    let detected_loop = self.loop_detector.detected_loop().unwrap();
+                    self.loop_duration = Some(detected_loop.length);
+                    if let Some(vgm_recorder) = &self.vgm_recorder {
+                        let time_base = self.options.video_options.video_time_base;
+                        let samples_per_frame = self.options.video_options.sample_rate as f64 * time_base.numerator() as f64 / time_base.denominator() as f64;
+                        let to_samples = |frames: u64| (frames as f64 * samples_per_frame).round() as u64;
+                        vgm_recorder.lock().unwrap().set_loop(to_samples(detected_loop.start), to_samples(detected_loop.length));
+                    }
+                    if let StopCondition::Loops(stop_loop_count) = self.stop_condition {
+                        let loop_frames = detected_loop.start + (detected_loop.length * stop_loop_count as u64);
+                        self.expected_duration = Some((loop_frames + self.fadeout_length) as usize);
+                    }
+                }
+            }
+            // An explicit length always wins over a guessed end
+            let explicit_length = matches!(self.stop_condition, StopCondition::Frames(_) | StopCondition::Time(_));
+            if self.loop_detector.ended() && !explicit_length {
+                self.last_position.end = true;
+            }
+        }
+
         Ok(true)
     }
 
     pub fn finish_encoding(&mut self) -> Result<()> {
         self.vb.finish_encoding()?;
+        if let Some(stems) = &mut self.stems {
+            stems.finish_encoding()?;
+        }
+        if let Some(midi) = &self.midi {
+            midi.lock().unwrap().write()?;
+        }
+        if let Some(vgm_recorder) = &self.vgm_recorder {
+            vgm_recorder.lock().unwrap().write()?;
+        }
 
         Ok(())
     }
 
+    /// Loudness of everything rendered so far, if this is a loudness pass
+    pub fn loudness(&self) -> Option<loudness::Loudness> {
+        self.loudness_meter.as_ref().map(|meter| meter.loudness())
+    }
+
     pub fn current_frame(&self) -> u64 {
         self.cur_frame
     }
@@ -361,19 +828,38 @@ impl Renderer {
         self.encode_start.elapsed()
     }
 
+    fn update_silence(&mut self, peak: u16) {
+        let threshold = match self.stop_condition {
+            StopCondition::Silence { threshold, .. } => threshold,
+            _ => render_options::DEFAULT_SILENCE_THRESHOLD
+        };
+        let peak = peak as f32 / i16::MAX as f32;
+        let max_volume = self.viz.lock().unwrap().max_channel_volume() / 15.0;
+        self.output_silent = peak < threshold && max_volume < threshold;
+
+        if let StopCondition::Silence { .. } = self.stop_condition {
+            if self.output_silent {
+                self.silent_frames += 1;
+            } else {
+                self.silent_frames = 0;
+            }
+        }
+    }
+
     fn next_expected_duration(&self) -> Option<usize> {
         if self.expected_duration.is_some() {
             return self.expected_duration;
         }
 
-        match self.options.stop_condition {
-            StopCondition::Frames(stop_frames) => Some((stop_frames + self.options.fadeout_length) as usize),
+        match self.stop_condition {
+            StopCondition::Frames(stop_frames) => Some((stop_frames + self.fadeout_length) as usize),
             StopCondition::Loops(stop_loop_count) => {
                 match self.loop_duration {
-                    Some(d) => Some(self.options.fadeout_length as usize + d as usize * stop_loop_count),
+                    Some(d) => Some(self.fadeout_length as usize + d as usize * stop_loop_count),
                     None => None
                 }
-            }
+            },
+            StopCondition::Time(_) | StopCondition::Silence { .. } | StopCondition::Playlist => None
         }
     }
 
@@ -383,24 +869,33 @@ impl Renderer {
             Some(t) => Some(t - 1),
             None => {
                 if self.last_position.end {
-                    return Some(self.options.fadeout_length);
+                    return Some(self.fadeout_length);
                 }
 
-                match self.options.stop_condition {
+                match self.stop_condition {
                     StopCondition::Loops(stop_loop_count) => {
                         if self.loop_count >= stop_loop_count as u64 {
-                            Some(self.options.fadeout_length)
+                            Some(self.fadeout_length)
                         } else {
                             None
                         }
                     },
                     StopCondition::Frames(stop_frames) => {
                         if self.current_frame() >= stop_frames {
-                            Some(self.options.fadeout_length)
+                            Some(self.fadeout_length)
                         } else {
                             None
                         }
-                    }
+                    },
+                    StopCondition::Silence { seconds, .. } => {
+                        // Nothing left to fade out
+                        if self.silent_frames as f64 >= seconds * self.frame_rate {
+                            Some(0)
+                        } else {
+                            None
+                        }
+                    },
+                    StopCondition::Time(_) | StopCondition::Playlist => None
                 }
             }
         }
@@ -408,8 +903,10 @@ impl Renderer {
 
     pub fn song_position(&mut self) -> Option<SongPosition> {
         match &self.options.input {
-            RenderInput::LSDj(_, _) => lsdj::get_song_position(&mut self.gb, &self.end_detector),
-            RenderInput::LSDj2x(_, _, _, _) => lsdj::get_song_position(&mut self.gb, &self.end_detector),
+            RenderInput::LSDj(_, _) => lsdj::get_song_position(&mut self.consoles[0], &self.end_detector),
+            RenderInput::LSDjSongs(_, _) => lsdj::get_song_position(&mut self.consoles[0], &self.end_detector),
+            RenderInput::LSDj2x(_, _, _, _) => lsdj::get_song_position(&mut self.consoles[0], &self.end_detector),
+            RenderInput::LSDjMulti(_) => lsdj::get_song_position(&mut self.consoles[0], &self.end_detector),
             _ => None
         }
     }
@@ -433,15 +930,15 @@ impl Renderer {
     }
 
     pub fn encode_rate(&self) -> f64 {
-        self.average_fps() as f64 / 60.0
+        self.average_fps() as f64 / self.frame_rate
     }
 
     pub fn encoded_duration(&self) -> Duration {
-        self.vb.encoded_video_duration()
+        self.vb.encoded_duration()
     }
 
     pub fn encoded_size(&self) -> usize {
-        self.vb.encoded_video_size()
+        self.vb.encoded_size()
     }
 
     pub fn expected_duration_frames(&self) -> Option<usize> {
@@ -451,7 +948,7 @@ impl Renderer {
     pub fn expected_duration(&self) -> Option<Duration> {
         match self.expected_duration {
             Some(d) => {
-                let secs = d as f64 / 60.0;
+                let secs = d as f64 / self.frame_rate;
                 Some(Duration::from_secs_f64(secs))
             },
             None => None
@@ -470,3 +967,54 @@ impl Renderer {
         }
     }
 }
+
+/// Create `count` consoles of the same model, with IDs matching their index
+fn new_consoles(count: usize, model: Model) -> Result<Vec<Gameboy>> {
+    (0..count).map(|id| Gameboy::new(id, model)).collect()
+}
+
+/// Scale interleaved samples by a gain per sample frame, the last gain covering any extra samples
+pub fn apply_gains<T: Copy + Into<f32>>(audio: &[T], gains: &[f64], channels: usize) -> Vec<i16> {
+    audio
+        .iter()
+        .enumerate()
+        .map(|(i, &s)| {
+            let gain = gains.get(i / channels.max(1)).or(gains.last()).copied().unwrap_or(1.0);
+            (Into::<f32>::into(s) as f64 * gain).round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+        })
+        .collect()
+}
+
+/// Forwards the APU state to several receivers, e.g. the visualizer and the MIDI recorder
+struct ApuStateSplitter(Vec<Arc<Mutex<dyn ApuStateReceiver>>>);
+
+impl ApuStateReceiver for ApuStateSplitter {
+    fn receive(&mut self, id: usize, channel: ApuChannel, volume: u8, amplitude: u8, frequency: f64, timbre: usize, balance: f64, edge: bool) {
+        for receiver in self.0.iter() {
+            receiver.lock().unwrap().receive(id, channel, volume, amplitude, frequency, timbre, balance, edge);
+        }
+    }
+}
+
+/// Forwards memory accesses to several interceptors, a write goes through only if none of them block it
+struct MemoryInterceptorSplitter(Vec<Arc<Mutex<dyn MemoryInterceptor>>>);
+
+impl MemoryInterceptor for MemoryInterceptorSplitter {
+    fn intercept_read(&mut self, id: usize, addr: u16, data: u8) -> u8 {
+        self.0
+            .iter()
+            .fold(data, |data, interceptor| interceptor.lock().unwrap().intercept_read(id, addr, data))
+    }
+
+    fn intercept_write(&mut self, id: usize, addr: u16, data: u8) -> bool {
+        self.0
+            .iter()
+            .fold(true, |allowed, interceptor| interceptor.lock().unwrap().intercept_write(id, addr, data) && allowed)
+    }
+
+    fn intercept_execute(&mut self, id: usize, addr: u16, opcode: u8) {
+        for interceptor in self.0.iter() {
+            interceptor.lock().unwrap().intercept_execute(id, addr, opcode);
+        }
+    }
+}
@@ -1,11 +1,17 @@
-use anyhow::bail;
+use anyhow::{Result, anyhow, bail, ensure};
 use std::str::FromStr;
 use std::ffi::OsStr;
-use sameboy::{Model, Revision};
+use sameboy::{Model, Revision, VideoStandard};
 use crate::config::Config;
+use crate::renderer::m3u_searcher;
 use crate::video_builder::video_options::VideoOptions;
 
-pub const FRAME_RATE: i32 = 60;
+pub const DMG_CLOCK_RATE: u32 = 4_194_304;
+// The SGB divides the SNES master clock by 5, the SGB2 has its own crystal at the DMG rate
+pub const SGB_NTSC_CLOCK_RATE: u32 = 21_477_272 / 5;
+pub const SGB_PAL_CLOCK_RATE: u32 = 21_281_370 / 5;
+pub const CLOCKS_PER_FRAME: u32 = 70_224;
+pub const DEFAULT_SILENCE_THRESHOLD: f32 = 0.001;
 
 macro_rules! extra_str_traits {
     ($t: ty) => {
@@ -25,8 +31,15 @@ macro_rules! extra_str_traits {
 
 #[derive(Copy, Clone)]
 pub enum StopCondition {
+    /// Seconds, converted to frames at the emulated model's frame rate
+    Time(f64),
     Frames(u64),
-    Loops(usize)
+    Loops(usize),
+    /// Stop after `seconds` where the output peak and every channel's volume stay below
+    /// `threshold` (as a fraction of full scale)
+    Silence { seconds: f64, threshold: f32 },
+    /// Use the track's length and fade from a NEZplug M3U playlist next to the GBS
+    Playlist
 }
 
 impl FromStr for StopCondition {
@@ -34,14 +47,15 @@ impl FromStr for StopCondition {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let parts: Vec<_> = s.split(':').collect();
-        if parts.len() != 2 {
-            bail!("Stop condition format invalid, try one of 'time:3', 'time:nsfe', 'frames:180', or 'loops:2'.");
+        if parts.len() != 2 && !(parts.len() == 3 && parts[0] == "silence") {
+            bail!("Stop condition format invalid, try one of 'time:3', 'time:m3u', 'time:nsfe', 'frames:180', 'loops:2', or 'silence:3'.");
         }
 
         match parts[0] {
+            "time" if parts[1] == "m3u" => Ok(StopCondition::Playlist),
             "time" => {
-                let time = u64::from_str(parts[1])?;
-                Ok(StopCondition::Frames(time * FRAME_RATE as u64))
+                let time = f64::from_str(parts[1])?;
+                Ok(StopCondition::Time(time))
             },
             "frames" => {
                 let frames = u64::from_str(parts[1])?;
@@ -51,20 +65,112 @@ impl FromStr for StopCondition {
                 let loops = usize::from_str(parts[1])?;
                 Ok(StopCondition::Loops(loops))
             },
-            _ => bail!("Unknown condition type {}. Valid types are 'time', 'frames', and 'loops'", parts[0])
+            "silence" => {
+                let seconds = f64::from_str(parts[1])?;
+                let threshold = match parts.get(2) {
+                    Some(threshold) => f32::from_str(threshold)?,
+                    None => DEFAULT_SILENCE_THRESHOLD
+                };
+                Ok(StopCondition::Silence { seconds, threshold })
+            },
+            _ => bail!("Unknown condition type {}. Valid types are 'time', 'frames', 'loops', and 'silence'", parts[0])
         }
     }
 }
 
 extra_str_traits!(StopCondition);
 
+#[derive(Copy, Clone)]
+pub enum StartPosition {
+    /// Seconds, converted to frames at the emulated model's frame rate
+    Time(f64),
+    Frames(u64),
+    /// LSDj song row, i.e. the row of chains in the song screen
+    Row(u8)
+}
+
+impl FromStr for StartPosition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split(':').collect();
+        if parts.len() != 2 {
+            bail!("Start position format invalid, try one of 'time:30', 'frames:1800', or 'row:1A'.");
+        }
+
+        match parts[0] {
+            "time" => {
+                let time = f64::from_str(parts[1])?;
+                Ok(StartPosition::Time(time))
+            },
+            "frames" => {
+                let frames = u64::from_str(parts[1])?;
+                Ok(StartPosition::Frames(frames))
+            },
+            "row" => {
+                // Hexadecimal, the way LSDj displays it
+                let row = u8::from_str_radix(parts[1], 16)?;
+                ensure!(row <= 0x7F, "LSDj song rows only go up to 7F!");
+                Ok(StartPosition::Row(row))
+            },
+            _ => bail!("Unknown position type {}. Valid types are 'time', 'frames', and 'row'", parts[0])
+        }
+    }
+}
+
+extra_str_traits!(StartPosition);
+
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum FadeCurve {
+    #[default]
+    Linear,
+    /// Linear in decibels, over a 60 dB range
+    Exponential,
+    EqualPower,
+    SCurve
+}
+
+impl FadeCurve {
+    /// Gain at `position` through a fade-in, from 0.0 (silent) to 1.0 (full volume). Fade-outs run it backwards.
+    pub fn gain(&self, position: f64) -> f64 {
+        let position = position.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => position,
+            FadeCurve::Exponential => (10f64.powf(3.0 * (position - 1.0)) - 0.001) / 0.999,
+            FadeCurve::EqualPower => (position * std::f64::consts::FRAC_PI_2).sin(),
+            FadeCurve::SCurve => position * position * (3.0 - 2.0 * position)
+        }
+    }
+}
+
+impl FromStr for FadeCurve {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(FadeCurve::Linear),
+            "exponential" | "exp" => Ok(FadeCurve::Exponential),
+            "equal-power" => Ok(FadeCurve::EqualPower),
+            "s-curve" => Ok(FadeCurve::SCurve),
+            _ => bail!("Unknown fade curve {}. Valid curves are 'linear', 'exponential', 'equal-power', and 's-curve'", s)
+        }
+    }
+}
+
+extra_str_traits!(FadeCurve);
+
 #[derive(Clone)]
 pub enum RenderInput {
     None,
     GBS(String),
     LSDj(String, String),
+    LSDjSongs(String, Vec<String>),
     LSDj2x(String, String, String, String),
-    VGM(String, u32, i32)
+    /// ROM/SAV pairs for any number of consoles that aren't linked, but start playing on the same frame
+    LSDjMulti(Vec<(String, String)>),
+    VGM(String, u32, i32),
+    UGE(String),
+    GBT(String)
 }
 
 #[derive(Clone)]
@@ -74,9 +180,35 @@ pub struct RendererOptions {
 
     pub track_index: u8,
     pub track_index_2x: u8,
+    /// Track indices for the third console onwards
+    pub extra_track_indices: Vec<u8>,
     pub stop_condition: StopCondition,
     pub fadeout_length: u64,
+    /// Fast-forward to this position without encoding anything before starting the output
+    pub start_at: Option<StartPosition>,
+    pub fadein_length: u64,
+    pub fade_curve: FadeCurve,
+    /// Also fade the video to and from black along with the audio
+    pub fade_video: bool,
     pub auto_lsdj_sync: bool,
+    /// Container extension for per-channel audio stems (e.g. "wav" or "flac"), if they should be exported
+    pub stem_format: Option<String>,
+    /// Mux each APU channel into the video as an extra audio stream after the full mix
+    pub stem_streams: bool,
+    /// Also write the APU activity to a Standard MIDI File next to the output
+    pub midi_export: bool,
+    /// Also record the APU register writes to a VGM file next to the output
+    pub vgm_export: bool,
+    /// Play VGMs by writing their registers directly instead of through the Pegmode driver
+    pub vgm_direct: bool,
+    /// Every track is rendered into one output, so per-track metadata is left out
+    pub album_mode: bool,
+    /// Integrated loudness in LUFS to normalize the audio to, after measuring it in a separate pass
+    pub loudness_target: Option<f64>,
+    /// Gain in dB applied to the mix and stems
+    pub gain_db: f64,
+    /// Only run the emulation and measure the mix's loudness, without writing any output
+    pub loudness_pass: bool,
 
     pub model: Model,
     pub config: Config
@@ -88,9 +220,10 @@ impl Default for RendererOptions {
             input: RenderInput::None,
             video_options: VideoOptions {
                 output_path: "".to_string(),
+                audio_only: false,
                 metadata: Default::default(),
                 background_path: None,
-                video_time_base: (70_224, 4_194_304).into(),
+                video_time_base: (CLOCKS_PER_FRAME as i32, DMG_CLOCK_RATE as i32).into(),
                 video_codec: "libx264".to_string(),
                 video_codec_params: Default::default(),
                 pixel_format_in: "rgba".to_string(),
@@ -101,15 +234,30 @@ impl Default for RendererOptions {
                 audio_codec: "aac".to_string(),
                 audio_codec_params: Default::default(),
                 audio_channels: 2,
+                audio_stem_titles: Vec::new(),
                 sample_format_in: "s16".to_string(),
                 sample_format_out: "fltp".to_string(),
                 sample_rate: 44_100,
             },
             track_index: 0,
             track_index_2x: 0,
-            stop_condition: StopCondition::Frames(300 * FRAME_RATE as u64),
+            extra_track_indices: Vec::new(),
+            stop_condition: StopCondition::Time(300.0),
             fadeout_length: 180,
+            start_at: None,
+            fadein_length: 0,
+            fade_curve: FadeCurve::Linear,
+            fade_video: false,
             auto_lsdj_sync: false,
+            stem_format: None,
+            stem_streams: false,
+            midi_export: false,
+            vgm_export: false,
+            vgm_direct: false,
+            album_mode: false,
+            loudness_target: None,
+            gain_db: 0.0,
+            loudness_pass: false,
             model: Model::DMG(Revision::RevB),
             config: Config::default()
         }
@@ -117,6 +265,88 @@ impl Default for RendererOptions {
 }
 
 impl RendererOptions {
+    /// The stop condition and fadeout length for the selected track, with times converted to frames
+    /// at `frame_rate` and playlist durations looked up
+    pub fn resolve_stop_condition(&self, frame_rate: f64) -> Result<(StopCondition, u64)> {
+        match self.stop_condition {
+            StopCondition::Time(seconds) => {
+                let stop_frames = (seconds * frame_rate).round() as u64;
+                return Ok((StopCondition::Frames(stop_frames), self.fadeout_length));
+            },
+            StopCondition::Playlist => (),
+            _ => return Ok((self.stop_condition, self.fadeout_length))
+        }
+
+        let gbs_path = match &self.input {
+            RenderInput::GBS(gbs_path) => gbs_path,
+            _ => bail!("M3U playlist durations are only supported for GBS files!")
+        };
+        let entries = m3u_searcher::search(gbs_path)
+            .map_err(|e| anyhow!("M3U search failed! {}", e))?;
+        let entry = entries.get(&self.track_index)
+            .ok_or(anyhow!("No M3U playlist entry found for track {}!", self.track_index + 1))?;
+        let play_length = entry.play_length()
+            .ok_or(anyhow!("M3U playlist entry for track {} has no duration!", self.track_index + 1))?;
+
+        let stop_frames = (play_length.as_secs_f64() * frame_rate).round() as u64;
+        let fadeout_length = match entry.fade {
+            Some(fade) => (fade.as_secs_f64() * frame_rate).round() as u64,
+            None => self.fadeout_length
+        };
+
+        Ok((StopCondition::Frames(stop_frames), fadeout_length))
+    }
+
+    /// Frame rate of the selected model, e.g. about 59.73 Hz for everything but the SGB
+    pub fn frame_rate(&self) -> f64 {
+        self.clock_rate() as f64 / CLOCKS_PER_FRAME as f64
+    }
+
+    /// CPU clock rate of the selected model, the same one SameBoy reports once it's running
+    pub fn clock_rate(&self) -> u32 {
+        match self.model {
+            Model::SGB(VideoStandard::NTSC, _) => SGB_NTSC_CLOCK_RATE,
+            Model::SGB(VideoStandard::PAL, _) => SGB_PAL_CLOCK_RATE,
+            _ => DMG_CLOCK_RATE
+        }
+    }
+
+    /// Track index to play on a console, the first one playing `track_index`
+    pub fn console_track_index(&self, console: usize) -> u8 {
+        match console {
+            0 => self.track_index,
+            1 => self.track_index_2x,
+            _ => self.extra_track_indices.get(console - 2).copied().unwrap_or_default()
+        }
+    }
+
+    /// The same render with every output disabled, for measuring its loudness
+    pub fn loudness_pass(&self) -> Self {
+        let mut options = self.clone();
+        options.loudness_pass = true;
+        options.gain_db = 0.0;
+        options.stem_format = None;
+        options.stem_streams = false;
+        options.midi_export = false;
+        options.vgm_export = false;
+        options.video_options.audio_only = true;
+        options
+    }
+
+    /// Adjust the video frame duration (given for the DMG clock) to the clock rate of the emulated model.
+    /// SGB models run off the SNES clock, which makes their frames slightly shorter.
+    pub fn adjust_time_base(&mut self, clock_rate: u32) {
+        let time_base = self.video_options.video_time_base;
+        self.video_options.video_time_base = match time_base.denominator() as u32 == DMG_CLOCK_RATE {
+            true => (time_base.numerator(), clock_rate as i32).into(),
+            // Keep approximated time bases (e.g. for QuickTime) within the same denominator
+            false => {
+                let numerator = time_base.numerator() as f64 * DMG_CLOCK_RATE as f64 / clock_rate as f64;
+                (numerator.round() as i32, time_base.denominator()).into()
+            }
+        };
+    }
+
     pub fn set_resolution_smart(&mut self, w: u32, h: u32) {
         self.video_options.resolution_out = (w, h);
 
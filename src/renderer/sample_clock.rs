@@ -0,0 +1,36 @@
+use sameboy::ApuChannel;
+
+/// Output sample count of every console, tracked through the APU state stream
+#[derive(Default)]
+pub struct SampleClock {
+    // Indexed by console ID
+    samples: Vec<u64>,
+    offset: u64
+}
+
+impl SampleClock {
+    pub fn receive(&mut self, id: usize, channel: ApuChannel) {
+        if self.samples.len() <= id {
+            self.samples.resize(id + 1, self.offset);
+        }
+        // Pulse 1 is always sent first for every sample
+        if channel == ApuChannel::Pulse1 {
+            self.samples[id] += 1;
+        }
+    }
+
+    pub fn console_samples(&self, id: usize) -> u64 {
+        self.samples.get(id).copied().unwrap_or(self.offset)
+    }
+
+    /// Samples of the console that's furthest ahead
+    pub fn samples(&self) -> u64 {
+        self.samples.iter().copied().max().unwrap_or(self.offset)
+    }
+
+    /// Move every console forward in time, e.g. over the gaps between album tracks
+    pub fn skip(&mut self, samples: u64) {
+        self.offset = self.samples() + samples;
+        self.samples.iter_mut().for_each(|s| *s = self.offset);
+    }
+}
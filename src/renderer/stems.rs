@@ -0,0 +1,221 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::path::Path;
+use sameboy::{ApuChannel, Gameboy, HighpassFilterMode, JoypadButton};
+use crate::renderer::apply_gains;
+use crate::video_builder::audio_builder::AudioBuilder;
+
+const CHANNELS: [(ApuChannel, &str); 4] = [
+    (ApuChannel::Pulse1, "Pulse 1"),
+    (ApuChannel::Pulse2, "Pulse 2"),
+    (ApuChannel::Wave, "Wave"),
+    (ApuChannel::Noise, "Noise")
+];
+
+/// What was loaded into a console, so its save state can be restored into another instance
+#[derive(Clone)]
+pub enum ConsoleImage {
+    Gbs(Vec<u8>),
+    Rom(Vec<u8>)
+}
+
+impl ConsoleImage {
+    fn load(&self, gb: &mut Gameboy) -> Result<()> {
+        match self {
+            ConsoleImage::Gbs(gbs) => {
+                gb.load_gbs(gbs).map_err(|e| anyhow!("Failed to load GBS for stem export! {}", e))?;
+            },
+            ConsoleImage::Rom(rom) => gb.load_rom(rom)
+        }
+        Ok(())
+    }
+}
+
+/// Stem names in output order, e.g. "Pulse 1" or "Pulse 1 (3x)" for the third console
+pub fn stem_titles(console_count: usize) -> Vec<String> {
+    (0..console_count)
+        .flat_map(|console| CHANNELS.iter().map(move |(_, name)| match console {
+            0 => name.to_string(),
+            _ => format!("{} ({}x)", name, console + 1)
+        }))
+        .collect()
+}
+
+struct StemConsole {
+    /// Index of the main console this stem's channel comes from
+    source: usize,
+    /// Copies of every linked console, or only the source one if the consoles aren't linked
+    consoles: Vec<Gameboy>
+}
+
+impl StemConsole {
+    /// Position of the source console's copy in `consoles`
+    fn source_index(&self) -> usize {
+        match self.consoles.len() {
+            1 => 0,
+            _ => self.source
+        }
+    }
+}
+
+/// Records each APU channel by running one extra emulator (or set of linked consoles) per channel in lockstep
+/// with the main one, with every other channel muted. Stems are optionally written to audio files.
+pub struct StemRecorder {
+    file_output: Option<(String, String)>,
+    sample_rate: i32,
+    metadata: HashMap<String, String>,
+    /// High-pass filter mode and interference volume, the same as the main console(s)
+    audio_settings: (HighpassFilterMode, f64),
+
+    consoles: Vec<StemConsole>,
+    builders: Vec<AudioBuilder>
+}
+
+fn mute_all_except(gb: &mut Gameboy, unmuted: Option<ApuChannel>) {
+    for (channel, _) in CHANNELS {
+        gb.apu_set_channel_muted(channel, Some(channel) != unmuted);
+    }
+}
+
+impl StemRecorder {
+    pub fn new(
+        output_path: &str,
+        file_extension: Option<&str>,
+        sample_rate: i32,
+        metadata: &HashMap<String, String>,
+        audio_settings: (HighpassFilterMode, f64)
+    ) -> Self {
+        let output_base = Path::new(output_path).with_extension("").to_str().unwrap().to_string();
+
+        Self {
+            file_output: file_extension.map(|extension| (output_base, extension.to_string())),
+            sample_rate,
+            metadata: metadata.clone(),
+            audio_settings,
+            consoles: Vec::new(),
+            builders: Vec::new()
+        }
+    }
+
+    /// Clone the current state of the main console(s) into a fresh set of stem consoles
+    pub fn attach(&mut self, consoles: &mut [Gameboy], images: &[ConsoleImage], linked: bool) -> Result<()> {
+        let model = consoles[0].model();
+        let states: Vec<Vec<u8>> = consoles.iter_mut().map(|gb| gb.save_state()).collect();
+        let console_count = consoles.len();
+
+        self.consoles.clear();
+        // The first IDs belong to the main consoles
+        let mut next_id = console_count;
+        for source in 0..console_count {
+            for (channel, _) in CHANNELS {
+                let copied: Vec<usize> = match linked {
+                    true => (0..console_count).collect(),
+                    false => vec![source]
+                };
+
+                let mut stem_consoles = Vec::with_capacity(copied.len());
+                for console in copied {
+                    let mut stem_gb = Gameboy::new(next_id, model)?;
+                    next_id += 1;
+                    self.configure(&mut stem_gb);
+                    images[console].load(&mut stem_gb)?;
+                    stem_gb.load_state(&states[console])?;
+                    mute_all_except(&mut stem_gb, if console == source { Some(channel) } else { None });
+                    stem_consoles.push(stem_gb);
+                }
+
+                if let [stem_gb, stem_gb_2x] = stem_consoles.as_mut_slice() {
+                    stem_gb.connect_console(stem_gb_2x);
+                }
+
+                self.consoles.push(StemConsole {
+                    source,
+                    consoles: stem_consoles
+                });
+            }
+        }
+
+        if let (Some((output_base, extension)), true) = (&self.file_output, self.builders.is_empty()) {
+            for title in stem_titles(console_count) {
+                let path = format!("{} - {}.{}", output_base, title, extension);
+
+                let mut builder = AudioBuilder::new(&path, self.sample_rate, 2, &self.metadata)?;
+                builder.start_encoding()?;
+                self.builders.push(builder);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn configure(&self, gb: &mut Gameboy) {
+        let (highpass_mode, interference) = self.audio_settings;
+        gb.set_sample_rate(self.sample_rate as usize);
+        gb.set_rendering_disabled(true);
+        gb.set_highpass_filter_mode(highpass_mode);
+        gb.set_interference_volume(interference);
+    }
+
+    /// Run every stem for a frame, pressing Start on the copies of the consoles in `start_pressed`
+    pub fn run_frame(&mut self, start_pressed: &[bool]) {
+        for stem in self.consoles.iter_mut() {
+            match stem.consoles.as_mut_slice() {
+                [gb] => { gb.run_frame(); },
+                consoles => Gameboy::run_frames_sync(consoles)
+            }
+
+            let pressed = match stem.consoles.len() {
+                1 => vec![start_pressed[stem.source]],
+                _ => start_pressed.to_vec()
+            };
+            for (gb, pressed) in stem.consoles.iter_mut().zip(pressed) {
+                if pressed {
+                    gb.set_joypad_button(JoypadButton::Start, true);
+                } else {
+                    gb.joypad_release_all();
+                }
+            }
+        }
+    }
+
+    /// Take the same amount of audio the main console produced, scaled the same way as the mix
+    pub fn push_audio(&mut self, frame_size: usize, gains: &[f64], channels: usize) -> Result<Vec<Option<Vec<i16>>>> {
+        let mut result: Vec<Option<Vec<i16>>> = Vec::with_capacity(self.consoles.len());
+
+        for (i, stem) in self.consoles.iter_mut().enumerate() {
+            // Drain the muted consoles too, so they don't pile up samples
+            let source_index = stem.source_index();
+            let mut audio = None;
+            for (j, gb) in stem.consoles.iter_mut().enumerate() {
+                let samples = gb.get_audio_samples(Some(frame_size));
+                if j == source_index {
+                    audio = samples;
+                }
+            }
+
+            let adjusted_audio: Option<Vec<i16>> = audio.map(|audio| apply_gains(&audio, gains, channels));
+            if let (Some(audio), Some(builder)) = (&adjusted_audio, self.builders.get_mut(i)) {
+                builder.push_audio_data(audio)?;
+            }
+            result.push(adjusted_audio);
+        }
+
+        Ok(result)
+    }
+
+    pub fn push_silence(&mut self, silence: &[i16]) -> Result<()> {
+        for builder in self.builders.iter_mut() {
+            builder.push_audio_data(silence)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish_encoding(&mut self) -> Result<()> {
+        for builder in self.builders.iter_mut() {
+            builder.finish_encoding()?;
+        }
+
+        Ok(())
+    }
+}
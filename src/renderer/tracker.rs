@@ -0,0 +1,73 @@
+use anyhow::{Result, ensure};
+use std::collections::HashMap;
+use crate::renderer::vgm::converter::EngineCommand;
+
+// Shared plumbing for tracker formats whose playback routines are ported to Rust. Their register
+// writes are recorded tick by tick and streamed through the VGM playback engine.
+
+// Period table used by both hUGEDriver and GBT Player, from C-3 to B-8. A few entries are rounded
+// differently than the equal temperament formula would, so the table is copied as-is.
+const NOTE_PERIODS: [u16; 72] = [
+    44, 156, 262, 363, 457, 547, 631, 710, 786, 854, 923, 986,
+    1046, 1102, 1155, 1205, 1253, 1297, 1339, 1379, 1417, 1452, 1486, 1517,
+    1546, 1575, 1602, 1627, 1650, 1673, 1694, 1714, 1732, 1750, 1767, 1783,
+    1798, 1812, 1825, 1837, 1849, 1860, 1871, 1881, 1890, 1899, 1907, 1915,
+    1923, 1930, 1936, 1943, 1949, 1954, 1959, 1964, 1969, 1974, 1978, 1982,
+    1985, 1988, 1992, 1995, 1998, 2001, 2004, 2006, 2009, 2011, 2013, 2015
+];
+const MAX_TICKS: usize = 60 * 60 * 60;
+
+pub trait TrackerPlayer {
+    /// Order and row about to be played, if the next tick starts a new row
+    fn row_start(&self) -> Option<(usize, usize)>;
+    fn step(&mut self);
+    fn take_writes(&mut self) -> Vec<(u16, u8)>;
+}
+
+pub struct PlayerOutput {
+    pub commands: Vec<EngineCommand>,
+    pub intro_ticks: usize,
+    pub loop_ticks: usize
+}
+
+pub fn note_period(note: i32) -> i32 {
+    NOTE_PERIODS[note.clamp(0, NOTE_PERIODS.len() as i32 - 1) as usize] as i32
+}
+
+pub fn record<P: TrackerPlayer>(player: &mut P) -> Result<PlayerOutput> {
+    let mut commands: Vec<EngineCommand> = Vec::new();
+    let mut row_starts: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+    let mut ticks = 0usize;
+
+    loop {
+        let row_start = player.row_start();
+        if let Some(position) = row_start {
+            if let Some(&(command_index, loop_start)) = row_starts.get(&position) {
+                commands.insert(command_index, EngineCommand::HitLoopOffset);
+                return Ok(PlayerOutput {
+                    commands,
+                    intro_ticks: loop_start,
+                    loop_ticks: ticks - loop_start
+                });
+            }
+            row_starts.insert(position, (commands.len(), ticks));
+        }
+
+        ensure!(ticks < MAX_TICKS, "Song does not loop!");
+
+        player.step();
+        ticks += 1;
+
+        let writes = player.take_writes();
+        if writes.is_empty() && row_start.is_none() {
+            // Merge consecutive waits, but never across a row start since the loop point may land there
+            if let Some(EngineCommand::WaitFrames(frames)) = commands.last_mut() {
+                *frames += 1;
+                continue;
+            }
+        }
+
+        commands.extend(writes.into_iter().map(|(addr, val)| EngineCommand::WriteLR35902Reg(false, addr, val)));
+        commands.push(EngineCommand::WaitFrames(1));
+    }
+}
@@ -0,0 +1,425 @@
+use anyhow::Result;
+use crate::renderer::tracker::{self, TrackerPlayer, PlayerOutput, note_period};
+use super::uge::{Uge, Cell, Instrument, NO_NOTE, PATTERN_ROWS, SUBPATTERN_BASE_NOTE};
+
+// Port of the hUGEDriver playback routine, used instead of linking the driver itself. It uses the driver's
+// note table and noise formula and ticks at the driver's rate, so every register write lands on the same tick.
+// 6xx is the one effect that can't be ported: it calls a routine that the song ships as assembly source.
+
+const CHANNEL_COUNT: usize = 4;
+const SUBPATTERN_ROWS: usize = 32;
+
+const NR10: u16 = 0xFF10;
+const NR30: u16 = 0xFF1A;
+const NR32: u16 = 0xFF1C;
+const NR50: u16 = 0xFF24;
+const NR51: u16 = 0xFF25;
+const NR52: u16 = 0xFF26;
+const WAVE_RAM: u16 = 0xFF30;
+
+const CHANNEL_BASES: [u16; CHANNEL_COUNT] = [0xFF10, 0xFF15, 0xFF1A, 0xFF1F];
+
+fn noise_polynomial(note: i32) -> u8 {
+    // hUGEDriver's get_note_poly: invert the note, then split it into a shift and a divisor
+    let value = 63 - note.clamp(0, 63);
+    match value {
+        0..=6 => value as u8,
+        _ => ((((value - 4) / 4) << 4) | ((value % 4) + 4)) as u8
+    }
+}
+
+#[derive(Clone, Default)]
+struct ChannelState {
+    instrument: Option<Instrument>,
+    note: i32,
+    period: i32,
+    target_period: i32,
+    volume: u8,
+    envelope: u8,
+    length_flag: u8,
+    wave: Option<u32>,
+    last_period: Option<i32>,
+    last_polynomial: Option<u8>,
+
+    effect_code: u8,
+    effect_param: u8,
+    vibrato_tick: u32,
+    vibrato_up: bool,
+    delayed_cell: Option<(u32, Cell)>,
+    cut_tick: Option<u32>,
+
+    subpattern_row: Option<usize>,
+    subpattern_offset: i32
+}
+
+struct Driver<'a> {
+    song: &'a Uge,
+    channels: [ChannelState; CHANNEL_COUNT],
+    ticks_per_row: u32,
+    tick: u32,
+    order: usize,
+    row: usize,
+    next_position: Option<(usize, usize)>,
+    writes: Vec<(u16, u8)>
+}
+
+impl<'a> Driver<'a> {
+    fn new(song: &'a Uge) -> Self {
+        Self {
+            song,
+            channels: Default::default(),
+            ticks_per_row: song.ticks_per_row,
+            tick: 0,
+            order: 0,
+            row: 0,
+            next_position: None,
+            writes: vec![(NR52, 0x80), (NR50, 0x77), (NR51, 0xFF)]
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.writes.push((addr, val));
+    }
+
+    fn instrument_bank(&self, channel: usize) -> &'a [Instrument] {
+        match channel {
+            0 | 1 => &self.song.duty_instruments,
+            2 => &self.song.wave_instruments,
+            _ => &self.song.noise_instruments
+        }
+    }
+
+    fn process_row(&mut self, channel: usize) {
+        let cell = self.song.cell(channel, self.order, self.row);
+        let param = cell.effect_param;
+
+        let state = &mut self.channels[channel];
+        state.effect_code = cell.effect_code;
+        state.effect_param = param;
+        state.delayed_cell = None;
+        state.cut_tick = None;
+
+        match cell.effect_code {
+            0x3 => {
+                // Tone portamento slides to the new note instead of retriggering
+                if cell.note != NO_NOTE {
+                    state.note = cell.note as i32;
+                    state.target_period = note_period(state.note);
+                }
+            }
+            0x7 if param > 0 => state.delayed_cell = Some((param as u32, cell)),
+            _ => if cell.note != NO_NOTE {
+                self.trigger(channel, cell);
+            }
+        }
+
+        match cell.effect_code {
+            0xB => self.next_position = Some((param as usize, 0)),
+            0xD => self.next_position = Some((self.order + 1, param as usize)),
+            0xE => self.channels[channel].cut_tick = Some(param as u32),
+            0xF if param > 0 => self.ticks_per_row = param as u32,
+            // Routines are Game Boy assembly, so there's nothing to call. uge_to_gbs warns about these.
+            0x6 => (),
+            code => self.apply_immediate_effect(channel, code, param)
+        }
+    }
+
+    fn apply_immediate_effect(&mut self, channel: usize, code: u8, param: u8) {
+        let base = CHANNEL_BASES[channel];
+        match code {
+            0x5 => self.write(NR50, param),
+            0x8 => self.write(NR51, param),
+            0x9 if channel < 2 => {
+                let length = self.channels[channel].instrument.as_ref().map(|i| i.length).unwrap_or_default();
+                self.write(base + 1, (param & 0xC0) | ((64 - length.min(64)) as u8 & 0x3F));
+            }
+            0x9 if channel == 2 => {
+                // On the wave channel, 9xx loads wave xx instead
+                let wave = (param & 0xF) as u32;
+                if self.channels[channel].wave != Some(wave) {
+                    self.load_wave(wave);
+                    if let Some(period) = self.channels[channel].last_period {
+                        let length_flag = self.channels[channel].length_flag;
+                        self.write(NR30, 0x80);
+                        self.write(base + 4, 0x80 | length_flag | (period >> 8) as u8);
+                    }
+                }
+            }
+            0x9 => {
+                // On the noise channel, 9xx switches between 15 bit (00) and 7 bit (80) noise
+                let state = &mut self.channels[channel];
+                if let Some(polynomial) = state.last_polynomial {
+                    let polynomial = (polynomial & !0x08) | ((param & 0x80) >> 4);
+                    state.last_polynomial = Some(polynomial);
+                    self.write(base + 3, polynomial);
+                }
+            }
+            0xC => {
+                let state = &mut self.channels[channel];
+                state.volume = param & 0xF;
+                state.envelope = param >> 4;
+                self.retrigger_volume(channel);
+            }
+            _ => ()
+        }
+    }
+
+    fn retrigger_volume(&mut self, channel: usize) {
+        let base = CHANNEL_BASES[channel];
+        let state = self.channels[channel].clone();
+        if state.last_period.is_none() && state.last_polynomial.is_none() {
+            return;
+        }
+
+        match channel {
+            2 => {
+                let output_level = match state.volume {
+                    0 => 0,
+                    1..=3 => 3,
+                    4..=7 => 2,
+                    _ => 1
+                };
+                self.write(NR32, output_level << 5);
+            }
+            _ => {
+                // Volume changes only take effect once the channel is retriggered
+                self.write(base + 2, (state.volume << 4) | state.envelope);
+                let high = match channel {
+                    3 => 0,
+                    _ => (state.last_period.unwrap_or_default() >> 8) as u8
+                };
+                self.write(base + 4, 0x80 | state.length_flag | high);
+            }
+        }
+    }
+
+    fn load_wave(&mut self, wave: u32) {
+        // The wave channel's DAC has to be off while wave RAM is written
+        let song = self.song;
+        self.write(NR30, 0x00);
+        if let Some(samples) = song.waves.get(wave as usize) {
+            for (i, &sample) in samples.iter().enumerate() {
+                self.write(WAVE_RAM + i as u16, sample);
+            }
+        }
+        self.channels[2].wave = Some(wave);
+    }
+
+    fn trigger(&mut self, channel: usize, cell: Cell) {
+        let base = CHANNEL_BASES[channel];
+        let bank = self.instrument_bank(channel);
+
+        let state = &mut self.channels[channel];
+        if let Some(instrument) = bank.get((cell.instrument as usize).wrapping_sub(1)) {
+            state.instrument = Some(instrument.clone());
+        }
+        let instrument = state.instrument.clone().unwrap_or(Instrument {
+            initial_volume: 15,
+            duty: 2,
+            output_level: 1,
+            ..Default::default()
+        });
+
+        state.note = cell.note as i32;
+        state.period = note_period(state.note);
+        state.target_period = state.period;
+        state.vibrato_tick = 0;
+        state.vibrato_up = false;
+        state.subpattern_row = instrument.subpattern.as_ref().map(|_| 0);
+        state.subpattern_offset = 0;
+        state.volume = instrument.initial_volume & 0xF;
+        state.envelope = ((instrument.volume_sweep_up as u8) << 3) | (instrument.volume_sweep_change & 7);
+        state.length_flag = if instrument.length_enabled { 0x40 } else { 0 };
+
+        let period = state.period;
+        let volume = state.volume;
+        let envelope = state.envelope;
+        let length_flag = state.length_flag;
+        let length = instrument.length as u8;
+
+        match channel {
+            0 | 1 => {
+                if channel == 0 {
+                    let sweep = ((instrument.freq_sweep_time as u8 & 7) << 4)
+                        | ((instrument.freq_sweep_down as u8) << 3)
+                        | (instrument.freq_sweep_shift as u8 & 7);
+                    self.write(NR10, sweep);
+                }
+                self.write(base + 1, ((instrument.duty & 3) << 6) | (64u8.wrapping_sub(length) & 0x3F));
+                self.write(base + 2, (volume << 4) | envelope);
+                self.write(base + 3, (period & 0xFF) as u8);
+                self.write(base + 4, 0x80 | length_flag | (period >> 8) as u8);
+                self.channels[channel].last_period = Some(period);
+            }
+            2 => {
+                if self.channels[channel].wave != Some(instrument.waveform) {
+                    self.load_wave(instrument.waveform);
+                }
+                self.write(NR30, 0x80);
+                self.write(base + 1, 0u8.wrapping_sub(length));
+                self.write(base + 2, (instrument.output_level as u8 & 3) << 5);
+                self.write(base + 3, (period & 0xFF) as u8);
+                self.write(base + 4, 0x80 | length_flag | (period >> 8) as u8);
+                self.channels[channel].last_period = Some(period);
+            }
+            _ => {
+                let polynomial = noise_polynomial(cell.note as i32) | ((instrument.noise_7bit as u8) << 3);
+                self.write(base + 1, 64u8.wrapping_sub(length) & 0x3F);
+                self.write(base + 2, (volume << 4) | envelope);
+                self.write(base + 3, polynomial);
+                self.write(base + 4, 0x80 | length_flag);
+                self.channels[channel].last_polynomial = Some(polynomial);
+            }
+        }
+    }
+
+    fn process_subpattern(&mut self, channel: usize) {
+        let state = &self.channels[channel];
+        let (row, subpattern) = match (state.subpattern_row, state.instrument.as_ref().and_then(|i| i.subpattern.as_ref())) {
+            (Some(row), Some(subpattern)) => (row, subpattern),
+            _ => return
+        };
+        let cell = subpattern[row];
+
+        let state = &mut self.channels[channel];
+        if cell.note != NO_NOTE {
+            state.subpattern_offset = cell.note as i32 - SUBPATTERN_BASE_NOTE as i32;
+        }
+        state.subpattern_row = match cell.jump {
+            0 if row + 1 < SUBPATTERN_ROWS => Some(row + 1),
+            0 => None,
+            jump => Some((jump as usize - 1) % SUBPATTERN_ROWS)
+        };
+
+        if matches!(cell.effect_code, 0x5 | 0x8 | 0x9 | 0xC) {
+            self.apply_immediate_effect(channel, cell.effect_code, cell.effect_param);
+        }
+    }
+
+    fn process_tick(&mut self, channel: usize) {
+        let base = CHANNEL_BASES[channel];
+
+        if let Some((tick, cell)) = self.channels[channel].delayed_cell {
+            if tick == self.tick {
+                self.channels[channel].delayed_cell = None;
+                if cell.note != NO_NOTE {
+                    self.trigger(channel, cell);
+                }
+            }
+        }
+
+        if self.channels[channel].cut_tick == Some(self.tick) {
+            self.channels[channel].cut_tick = None;
+            match channel {
+                2 => self.write(NR30, 0x00),
+                _ => self.write(base + 2, 0x00)
+            }
+        }
+
+        self.process_subpattern(channel);
+
+        let tick = self.tick;
+        let state = &mut self.channels[channel];
+        let param = state.effect_param as i32;
+        let mut note_offset = state.subpattern_offset;
+        let mut vibrato = 0;
+
+        match state.effect_code {
+            0x0 if param != 0 => note_offset += [0, param >> 4, param & 0xF][(tick % 3) as usize],
+            0x1 if tick != 0 => state.period = (state.period + param).min(2047),
+            0x2 if tick != 0 => state.period = (state.period - param).max(0),
+            0x3 if tick != 0 => {
+                state.period = if state.period < state.target_period {
+                    (state.period + param).min(state.target_period)
+                } else {
+                    (state.period - param).max(state.target_period)
+                };
+            }
+            0x4 => {
+                // The driver toggles the offset on every tick where the counter masked by the speed is 0
+                let (speed, depth) = ((param as u32) >> 4, param & 0xF);
+                if state.vibrato_tick & speed == 0 {
+                    state.vibrato_up = !state.vibrato_up;
+                }
+                if state.vibrato_up {
+                    vibrato = depth;
+                }
+                state.vibrato_tick += 1;
+            }
+            0xA if tick != 0 && channel != 2 => {
+                state.volume = (state.volume as i32 + (param >> 4) - (param & 0xF)).clamp(0, 15) as u8;
+                state.envelope = 0;
+                self.retrigger_volume(channel);
+            }
+            _ => ()
+        }
+
+        let state = &mut self.channels[channel];
+        match channel {
+            3 => {
+                if state.last_polynomial.is_none() {
+                    return;
+                }
+                let width = state.last_polynomial.unwrap() & 0x08;
+                let polynomial = noise_polynomial(state.note + note_offset) | width;
+                if state.last_polynomial != Some(polynomial) {
+                    state.last_polynomial = Some(polynomial);
+                    self.write(base + 3, polynomial);
+                }
+            }
+            _ => {
+                if state.last_period.is_none() {
+                    return;
+                }
+                let period = (state.period + note_period(state.note + note_offset) - note_period(state.note) + vibrato)
+                    .clamp(0, 2047);
+                if state.last_period != Some(period) {
+                    let length_flag = state.length_flag;
+                    state.last_period = Some(period);
+                    self.write(base + 3, (period & 0xFF) as u8);
+                    self.write(base + 4, length_flag | (period >> 8) as u8);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> TrackerPlayer for Driver<'a> {
+    fn row_start(&self) -> Option<(usize, usize)> {
+        match self.tick {
+            0 => Some((self.order, self.row)),
+            _ => None
+        }
+    }
+
+    fn step(&mut self) {
+        if self.tick == 0 {
+            for channel in 0..CHANNEL_COUNT {
+                self.process_row(channel);
+            }
+        }
+        for channel in 0..CHANNEL_COUNT {
+            self.process_tick(channel);
+        }
+
+        self.tick += 1;
+        if self.tick >= self.ticks_per_row {
+            self.tick = 0;
+
+            let (order, row) = self.next_position.take().unwrap_or(match self.row + 1 {
+                PATTERN_ROWS => (self.order + 1, 0),
+                row => (self.order, row)
+            });
+            self.order = if order < self.song.order_length() { order } else { 0 };
+            self.row = row.min(PATTERN_ROWS - 1);
+        }
+    }
+
+    fn take_writes(&mut self) -> Vec<(u16, u8)> {
+        std::mem::take(&mut self.writes)
+    }
+}
+
+pub fn run(song: &Uge) -> Result<PlayerOutput> {
+    tracker::record(&mut Driver::new(song))
+}
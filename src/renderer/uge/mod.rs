@@ -0,0 +1,34 @@
+mod uge;
+mod driver;
+
+use anyhow::Result;
+use crate::renderer::vgm::converter;
+
+pub use uge::Uge;
+
+pub fn uge_to_gbs(song: &Uge) -> Result<Vec<u8>> {
+    if song.calls_routines() {
+        println!("Warning: the song calls routines (6xx), which are Game Boy assembly and won't be played");
+    }
+
+    let output = driver::run(song)?;
+    let engine_data = converter::commands_to_engine_format(output.commands, false, false)?;
+    let (tma, tac) = engine_timer(song);
+
+    Ok(converter::engine_data_to_gbs(&engine_data, tma, tac, &song.name, &song.artist, &song.comment))
+}
+
+pub fn duration_frames(song: &Uge, loops: usize) -> Result<usize> {
+    let output = driver::run(song)?;
+    let ticks = output.intro_ticks + (loops * output.loop_ticks);
+    let (tma, tac) = engine_timer(song);
+
+    Ok(converter::engine_ticks_to_frames(ticks as u64, tma, tac).round() as usize)
+}
+
+fn engine_timer(song: &Uge) -> (u8, u8) {
+    match song.timer_divider {
+        Some(divider) => (0u8.wrapping_sub(divider), 4u8),
+        None => (0u8, 0u8)
+    }
+}
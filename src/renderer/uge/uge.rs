@@ -0,0 +1,272 @@
+use anyhow::{Result, ensure, Context};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use encoding_rs::WINDOWS_1252;
+
+pub const NO_NOTE: u32 = 90;
+pub const PATTERN_ROWS: usize = 64;
+pub const INSTRUMENT_COUNT: usize = 15;
+pub const WAVE_COUNT: usize = 16;
+/// Subpattern note that leaves the pitch unchanged
+pub const SUBPATTERN_BASE_NOTE: u32 = 36;
+
+const SHORTSTRING_SIZE: usize = 256;
+const NOISE_INSTRUMENT_TYPE: u32 = 2;
+
+#[derive(Copy, Clone, Default)]
+pub struct Cell {
+    pub note: u32,
+    pub instrument: u32,
+    /// Only used by subpattern rows: 1-based row to jump to, or 0 to go to the next row
+    pub jump: u32,
+    pub effect_code: u8,
+    pub effect_param: u8
+}
+
+#[derive(Clone, Default)]
+pub struct Instrument {
+    pub length: u32,
+    pub length_enabled: bool,
+    pub initial_volume: u8,
+    pub volume_sweep_up: bool,
+    pub volume_sweep_change: u8,
+    pub freq_sweep_time: u32,
+    pub freq_sweep_down: bool,
+    pub freq_sweep_shift: u32,
+    pub duty: u8,
+    pub output_level: u32,
+    pub waveform: u32,
+    pub noise_7bit: bool,
+    pub subpattern: Option<Vec<Cell>>
+}
+
+pub struct Uge {
+    pub name: String,
+    pub artist: String,
+    pub comment: String,
+    pub duty_instruments: Vec<Instrument>,
+    pub wave_instruments: Vec<Instrument>,
+    pub noise_instruments: Vec<Instrument>,
+    pub waves: Vec<[u8; 16]>,
+    pub ticks_per_row: u32,
+    pub timer_divider: Option<u8>,
+    pub patterns: HashMap<u32, Vec<Cell>>,
+    pub orders: [Vec<u32>; 4]
+}
+
+struct UgeReader<'a> {
+    data: &'a [u8],
+    ptr: usize
+}
+
+impl<'a> UgeReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let result = self.data
+            .get(self.ptr..(self.ptr + len))
+            .context("Unexpected end of UGE data!")?;
+        self.ptr += len;
+        Ok(result)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    /// Pascal shortstring: a length byte followed by 255 bytes of storage
+    fn shortstring(&mut self) -> Result<String> {
+        let raw = self.bytes(SHORTSTRING_SIZE)?;
+        let len = raw[0] as usize;
+        let (s, _, _) = WINDOWS_1252.decode(&raw[1..(1 + len)]);
+        Ok(s.trim().to_string())
+    }
+
+    fn cell(&mut self, version: u32, is_subpattern: bool) -> Result<Cell> {
+        let mut result = Cell::default();
+
+        result.note = self.u32()?;
+        if is_subpattern {
+            let _unused = self.u32()?;
+            result.jump = self.u32()?;
+        } else {
+            result.instrument = self.u32()?;
+            if version >= 6 {
+                let _volume = self.u32()?;
+            }
+        }
+        result.effect_code = self.u32()? as u8;
+        result.effect_param = self.u8()?;
+
+        Ok(result)
+    }
+
+    fn instrument(&mut self, version: u32) -> Result<Instrument> {
+        let mut result = Instrument::default();
+
+        let instrument_type = self.u32()?;
+        let _name = self.shortstring()?;
+        result.length = self.u32()?;
+        result.length_enabled = self.bool()?;
+        result.initial_volume = self.u8()?;
+        result.volume_sweep_up = self.u32()? == 0;
+        result.volume_sweep_change = self.u8()?;
+        result.freq_sweep_time = self.u32()?;
+        result.freq_sweep_down = self.u32()? != 0;
+        result.freq_sweep_shift = self.u32()?;
+        result.duty = self.u8()?;
+        result.output_level = self.u32()?;
+        result.waveform = self.u32()?;
+        result.noise_7bit = self.u32()? != 0;
+
+        if version >= 6 {
+            let subpattern_enabled = self.bool()?;
+            let subpattern = (0..PATTERN_ROWS)
+                .map(|_| self.cell(version, true))
+                .collect::<Result<Vec<_>>>()?;
+            if subpattern_enabled {
+                result.subpattern = Some(subpattern);
+            }
+        } else {
+            // Noise macros were replaced by subpatterns in v6, so convert them the same way hUGETracker does:
+            // the macro's note offsets play on the ticks after the trigger, then the last one holds
+            let noise_macro = self.bytes(6)?;
+            if instrument_type == NOISE_INSTRUMENT_TYPE && noise_macro.iter().any(|&offset| offset != 0) {
+                let mut subpattern = vec![Cell { note: NO_NOTE, ..Default::default() }; PATTERN_ROWS];
+                subpattern[0].note = SUBPATTERN_BASE_NOTE;
+                for (cell, &offset) in subpattern[1..].iter_mut().zip(noise_macro.iter()) {
+                    cell.note = (SUBPATTERN_BASE_NOTE as i32 + offset as i8 as i32) as u32;
+                }
+                result.subpattern = Some(subpattern);
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+impl Uge {
+    pub fn new(data: &[u8]) -> Result<Self> {
+        let mut reader = UgeReader { data, ptr: 0 };
+
+        let version = reader.u32()?;
+        ensure!((4..=6).contains(&version), "UGE version {} not supported", version);
+
+        let name = reader.shortstring()?;
+        let artist = reader.shortstring()?;
+        let comment = reader.shortstring()?;
+
+        let mut instruments: Vec<Vec<Instrument>> = Vec::with_capacity(3);
+        for _ in 0..3 {
+            instruments.push(
+                (0..INSTRUMENT_COUNT)
+                    .map(|_| reader.instrument(version))
+                    .collect::<Result<Vec<_>>>()?
+            );
+        }
+        let noise_instruments = instruments.pop().unwrap();
+        let wave_instruments = instruments.pop().unwrap();
+        let duty_instruments = instruments.pop().unwrap();
+
+        let mut waves: Vec<[u8; 16]> = Vec::with_capacity(WAVE_COUNT);
+        for _ in 0..WAVE_COUNT {
+            let mut wave = [0u8; 16];
+            for (i, samples) in reader.bytes(32)?.chunks_exact(2).enumerate() {
+                wave[i] = ((samples[0] & 0xF) << 4) | (samples[1] & 0xF);
+            }
+            waves.push(wave);
+        }
+
+        let ticks_per_row = reader.u32()?;
+        ensure!(ticks_per_row > 0, "UGE has an invalid tempo!");
+
+        let mut timer_divider: Option<u8> = None;
+        if version >= 6 {
+            let timer_enabled = reader.bool()?;
+            let divider = reader.u32()? as u8;
+            if timer_enabled {
+                ensure!(divider > 0, "UGE has an invalid timer tempo divider!");
+                timer_divider = Some(divider);
+            }
+        }
+
+        let pattern_count = reader.u32()?;
+        let mut patterns: HashMap<u32, Vec<Cell>> = HashMap::new();
+        for i in 0..pattern_count {
+            let index = if version >= 5 {
+                reader.u32()?
+            } else {
+                i
+            };
+            let rows = (0..PATTERN_ROWS)
+                .map(|_| reader.cell(version, false))
+                .collect::<Result<Vec<_>>>()?;
+            patterns.insert(index, rows);
+        }
+
+        let mut orders: [Vec<u32>; 4] = Default::default();
+        for order in orders.iter_mut() {
+            // The stored length is off by one, and the extra entry is garbage
+            let length = reader.u32()? as usize;
+            ensure!(length > 0, "UGE has an invalid order list!");
+            *order = (0..length)
+                .map(|_| reader.u32())
+                .collect::<Result<Vec<_>>>()?;
+            order.pop();
+        }
+
+        let order_length = orders[0].len();
+        ensure!(order_length > 0, "UGE order list is empty!");
+        ensure!(orders.iter().all(|o| o.len() == order_length), "UGE order lists have different lengths!");
+        for index in orders.iter().flatten() {
+            ensure!(patterns.contains_key(index), "UGE order list references missing pattern {}", index);
+        }
+
+        // Routines (6xx effects) follow, but they're Game Boy assembly source and can't be used here
+
+        Ok(Self {
+            name,
+            artist,
+            comment,
+            duty_instruments,
+            wave_instruments,
+            noise_instruments,
+            waves,
+            ticks_per_row,
+            timer_divider,
+            patterns,
+            orders
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = fs::read(path)?;
+        Self::new(&data)
+    }
+
+    pub fn order_length(&self) -> usize {
+        self.orders[0].len()
+    }
+
+    pub fn cell(&self, channel: usize, order: usize, row: usize) -> Cell {
+        self.patterns[&self.orders[channel][order]][row]
+    }
+
+    /// Whether any pattern or subpattern calls a routine (6xx)
+    pub fn calls_routines(&self) -> bool {
+        let subpatterns = self.duty_instruments.iter()
+            .chain(&self.wave_instruments)
+            .chain(&self.noise_instruments)
+            .filter_map(|instrument| instrument.subpattern.as_ref());
+        self.patterns.values().chain(subpatterns).flatten().any(|cell| cell.effect_code == 0x6)
+    }
+}
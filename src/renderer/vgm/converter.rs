@@ -7,14 +7,27 @@ const WRITE_HRAM_CMD: u8 = 0xB0;
 const LOOP_CMD: u8 = 0xC0;
 const END_SONG_CMD: u8 = 0xD0;
 
+const SYNC_BLOCK_TYPE: u8 = 0x00;
+
 const VGM_SAMPLE_RATE: f32 = 44100.0;
 const TMA_RATE_0: u32 = 4096;
+// The timer (4096 Hz on a DMG) and the frames both run off the CPU clock, so this holds for every model
+const CLOCKS_PER_TIMER_TICK: f64 = 1024.0;
+const CLOCKS_PER_FRAME: f64 = 70224.0;
 
 pub struct PegmodeEngineData {
     pub banks: Vec<Vec<u8>>,
     pub loop_data: Option<(usize, usize)>
 }
 
+#[derive(Clone, Debug)]
+pub enum EngineCommand {
+    HitLoopOffset,
+    WaitFrames(u32),
+    WriteHRAM(u8, u8),
+    WriteLR35902Reg(bool, u16, u8)
+}
+
 pub fn samples_to_frames(samples: u32, engine_rate: u32) -> u32 {
     let frames = (samples as f32 * engine_rate as f32) / VGM_SAMPLE_RATE;
     if 0.0 < frames.fract() && frames.fract() < 0.98 {
@@ -27,16 +40,65 @@ fn calculate_tma_modulo(tma_rate: u32, engine_rate: u32) -> i32 {
     0xFF - (tma_rate as f32 / engine_rate as f32).round() as i32
 }
 
-fn vgm_to_engine_format(vgm: &mut Vgm, for_2x: bool, engine_rate: u32) -> Result<PegmodeEngineData> {
+/// TMA and TAC the engine runs with, 60 Hz meaning VBlank
+pub fn engine_timer(engine_rate: u32, tma_offset: i32) -> (u8, u8) {
+    match engine_rate {
+        60 => (tma_offset as u8, 0u8),
+        _ => ((calculate_tma_modulo(TMA_RATE_0, engine_rate) + tma_offset) as u8, 4u8)
+    }
+}
+
+/// Convert engine ticks to frames for the timer the engine actually gets
+pub fn engine_ticks_to_frames(ticks: u64, tma: u8, tac: u8) -> f64 {
+    match tac & 4 {
+        0 => ticks as f64,
+        _ => ticks as f64 * (0x100 - tma as u32) as f64 * CLOCKS_PER_TIMER_TICK / CLOCKS_PER_FRAME
+    }
+}
+
+/// Deflemask stores its sync writes as one byte data blocks of type $00. Everything else is data for other
+/// chips: PCM streams ($00-$3F), compressed streams ($40-$7F), ROM images ($80-$BF) and RAM writes ($C0-$FF).
+fn sync_write(block_type: u8, block: &[u8]) -> Option<u8> {
+    match (block_type, block) {
+        (SYNC_BLOCK_TYPE, &[value]) => Some(value),
+        _ => None
+    }
+}
+
+fn vgm_to_engine_commands(vgm: &Vgm, engine_rate: u32) -> Result<Vec<EngineCommand>> {
+    let mut result: Vec<EngineCommand> = Vec::new();
+    for command in vgm.iter() {
+        match command {
+            VgmIterItem::HitLoopOffset => result.push(EngineCommand::HitLoopOffset),
+            VgmIterItem::WaitCommand(samples) => {
+                result.push(EngineCommand::WaitFrames(samples_to_frames(samples as u32, engine_rate)));
+            }
+            VgmIterItem::DataBlock(block_type, block) => {
+                if let Some(value) = sync_write(block_type, &block) {
+                    result.push(EngineCommand::WriteHRAM(0x80, value));
+                }
+            }
+            VgmIterItem::OtherChipCommand(_) => (),
+            VgmIterItem::WriteLR35902RegCommand(cmd_is_2x, addr, val) => {
+                result.push(EngineCommand::WriteLR35902Reg(cmd_is_2x, addr, val));
+            }
+            VgmIterItem::InvalidCommand(cmd) => {
+                bail!("Invalid/unsupported VGM command {:02X}!", cmd);
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+pub fn commands_to_engine_format<I: IntoIterator<Item = EngineCommand>>(commands: I, is_2x: bool, for_2x: bool) -> Result<PegmodeEngineData> {
     let mut result = PegmodeEngineData {
         banks: Vec::new(),
         loop_data: None
     };
     let mut current_bank: Vec<u8> = Vec::new();
 
-    let (_clock, is_2x) = vgm.lr35902_clock().context("VGM does not have a Game Boy!")?;
-
-    for command in vgm.iter() {
+    for command in commands {
         if current_bank.len() >= 0x3FFC {
             current_bank.push(NEXT_BANK_CMD);
             result.banks.push(current_bank.clone());
@@ -44,13 +106,12 @@ fn vgm_to_engine_format(vgm: &mut Vgm, for_2x: bool, engine_rate: u32) -> Result
         }
 
         match command {
-            VgmIterItem::HitLoopOffset => {
+            EngineCommand::HitLoopOffset => {
                 let loop_bank = result.banks.len() + 1;
                 let loop_addr = current_bank.len() + 0x4000;
                 result.loop_data = Some((loop_bank, loop_addr));
             }
-            VgmIterItem::WaitCommand(samples) => {
-                let mut frames = samples_to_frames(samples as u32, engine_rate);
+            EngineCommand::WaitFrames(mut frames) => {
                 while frames > 0xFF {
                     current_bank.push(WAIT_CMD);
                     current_bank.push(0xFF);
@@ -59,13 +120,12 @@ fn vgm_to_engine_format(vgm: &mut Vgm, for_2x: bool, engine_rate: u32) -> Result
                 current_bank.push(WAIT_CMD);
                 current_bank.push(frames as u8);
             }
-            VgmIterItem::DataBlock(_, block) => {
-                // Used for Deflemask sync writes
+            EngineCommand::WriteHRAM(h_addr, val) => {
                 current_bank.push(WRITE_HRAM_CMD);
-                current_bank.push(0x80);
-                current_bank.push(block[0]);
+                current_bank.push(h_addr);
+                current_bank.push(val);
             }
-            VgmIterItem::WriteLR35902RegCommand(cmd_is_2x, addr, val) => {
+            EngineCommand::WriteLR35902Reg(cmd_is_2x, addr, val) => {
                 if cmd_is_2x && !is_2x {
                     bail!("Encountered 2x write in non-2x VGM");
                 }
@@ -79,9 +139,6 @@ fn vgm_to_engine_format(vgm: &mut Vgm, for_2x: bool, engine_rate: u32) -> Result
                     current_bank.push(0x01);
                 }
             }
-            VgmIterItem::InvalidCommand(cmd) => {
-                bail!("Invalid/unsupported VGM command {:02X}!", cmd);
-            }
         }
     }
 
@@ -96,14 +153,18 @@ fn vgm_to_engine_format(vgm: &mut Vgm, for_2x: bool, engine_rate: u32) -> Result
 }
 
 fn metadata_string(s: &str) -> Vec<u8> {
-    let mut buf = s.to_owned().into_bytes();
+    // GBS header strings are 32 bytes, cut at a character boundary so multi-byte characters stay valid
+    let mut length = s.len().min(0x20);
+    while !s.is_char_boundary(length) {
+        length -= 1;
+    }
+
+    let mut buf = s[..length].as_bytes().to_vec();
     buf.resize(0x20, 0);
     buf
 }
 
-pub fn vgm_to_gbs(vgm: &mut Vgm, for_2x: bool, engine_rate: u32, tma_offset: i32) -> Result<Vec<u8>> {
-    let engine_data = vgm_to_engine_format(vgm, for_2x, engine_rate)?;
-
+pub fn engine_data_to_gbs(engine_data: &PegmodeEngineData, tma: u8, tac: u8, title: &str, author: &str, copyright: &str) -> Vec<u8> {
     let mut patch_rom = include_bytes!("patch_rom.gb").to_vec();
     patch_rom.resize(patch_rom.len() + (engine_data.banks.len() + 1) * 0x4000, 0);
 
@@ -116,11 +177,6 @@ pub fn vgm_to_gbs(vgm: &mut Vgm, for_2x: bool, engine_rate: u32, tma_offset: i32
         patch_rom[0x3FFE..0x4000].copy_from_slice(&u16::to_le_bytes(loop_bank as u16));
     }
 
-    let (tma, tac) = match engine_rate {
-        60 => (tma_offset as u8, 0u8),
-        _ => ((calculate_tma_modulo(TMA_RATE_0, engine_rate) + tma_offset) as u8, 4u8)
-    };
-
     patch_rom[0x3FFA] = tma; // Engine TMA
     patch_rom[0x3FFB] = tac; // Engine TAC
 
@@ -138,19 +194,51 @@ pub fn vgm_to_gbs(vgm: &mut Vgm, for_2x: bool, engine_rate: u32, tma_offset: i32
 
     debug_assert_eq!(gbs.len(), 0x10);
 
-    if let Some(gd3) = vgm.gd3_metadata() {
-        gbs.extend(metadata_string(&gd3.title));
-        gbs.extend(metadata_string(&gd3.author));
-        gbs.extend(metadata_string(&gd3.game));
-    } else {
-        gbs.extend(metadata_string("<?>"));
-        gbs.extend(metadata_string("<?>"));
-        gbs.extend(metadata_string("<?>"));
-    }
+    gbs.extend(metadata_string(title));
+    gbs.extend(metadata_string(author));
+    gbs.extend(metadata_string(copyright));
 
     debug_assert_eq!(gbs.len(), 0x70);
 
     gbs.extend_from_slice(&patch_rom[0x3EF0..]);
 
+    gbs
+}
+
+/// A GBS that does nothing, for consoles whose registers are written directly
+pub fn idle_gbs(title: &str, author: &str, copyright: &str) -> Vec<u8> {
+    let mut gbs: Vec<u8> = Vec::with_capacity(0x71);
+    gbs.extend(b"GBS"); // Magic
+    gbs.push(1); // Version
+    gbs.push(1); // Song count
+    gbs.push(1); // First song index
+    gbs.extend(&u16::to_le_bytes(0x0400)); // Load address
+    gbs.extend(&u16::to_le_bytes(0x0400)); // Init address
+    gbs.extend(&u16::to_le_bytes(0x0400)); // Play address
+    gbs.extend(&u16::to_le_bytes(0xFFFE)); // Stack pointer
+    gbs.push(0); // Timer TMA
+    gbs.push(0); // Timer TAC
+
+    gbs.extend(metadata_string(title));
+    gbs.extend(metadata_string(author));
+    gbs.extend(metadata_string(copyright));
+
+    gbs.push(0xC9); // ret
+
+    gbs
+}
+
+pub fn vgm_to_gbs(vgm: &mut Vgm, for_2x: bool, engine_rate: u32, tma_offset: i32) -> Result<Vec<u8>> {
+    let (_clock, is_2x) = vgm.lr35902_clock().context("VGM does not have a Game Boy!")?;
+    let commands = vgm_to_engine_commands(vgm, engine_rate)?;
+    let engine_data = commands_to_engine_format(commands, is_2x, for_2x)?;
+
+    let (tma, tac) = engine_timer(engine_rate, tma_offset);
+
+    let gbs = match vgm.gd3_metadata() {
+        Some(gd3) => engine_data_to_gbs(&engine_data, tma, tac, &gd3.title, &gd3.author, &gd3.game),
+        None => engine_data_to_gbs(&engine_data, tma, tac, "<?>", "<?>", "<?>")
+    };
+
     Ok(gbs)
 }
@@ -1,20 +1,24 @@
-use anyhow::{Result, Context, bail};
-use std::slice;
+use anyhow::{Result, bail};
 
 #[derive(Clone, Default)]
 pub struct Gd3 {
     pub version: u32,
     pub title: String,
+    pub title_jp: String,
     pub game: String,
+    pub game_jp: String,
     pub system: String,
+    pub system_jp: String,
     pub author: String,
+    pub author_jp: String,
+    pub date: String,
     pub ripper: String,
     pub notes: String
 }
 
 impl Gd3 {
     pub fn new(data: &[u8]) -> Result<Self> {
-        if &data[0..4] != b"Gd3 " {
+        if data.len() < 0xC || &data[0..4] != b"Gd3 " {
             bail!("Invalid GD3 metadata!");
         }
 
@@ -26,39 +30,74 @@ impl Gd3 {
         buf.copy_from_slice(&data[0x8..0xC]);
         let data_size = u32::from_le_bytes(buf) as usize;
 
-        let utf16_string_slice = unsafe {
-            slice::from_raw_parts(
-                (&data[0xC..(0xC + data_size)]).as_ptr() as *const u16,
-                data_size
-            )
-        };
-        let string_table_bytes = String::from_utf16(utf16_string_slice)
-            .context("Invalid GD3 string table!")?
-            .into_bytes();
-        let mut string_table = string_table_bytes.split(|c| *c == 0);
-
-        result.title = String::from_utf8(string_table.next().context("Missing title!")?.to_vec())?;
-
-        string_table.next().context("Missing original title!")?;
-
-        result.game = String::from_utf8(string_table.next().context("Missing game name!")?.to_vec())?;
-
-        string_table.next().context("Missing original game name!")?;
-
-        result.system = String::from_utf8(string_table.next().context("Missing system name!")?.to_vec())?;
-
-        string_table.next().context("Missing original system name!")?;
-
-        result.author = String::from_utf8(string_table.next().context("Missing author name!")?.to_vec())?;
-
-        string_table.next().context("Missing original author name!")?;
+        // The size is in bytes, and some rippers write a size past the end of the file
+        let string_table_data = &data[0xC..(0xC + data_size).min(data.len())];
+        let utf16_string_table: Vec<u16> = string_table_data
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        // Missing trailing fields are left empty
+        let mut string_table = utf16_string_table
+            .split(|c| *c == 0)
+            .map(String::from_utf16_lossy);
+
+        for field in [
+            &mut result.title, &mut result.title_jp,
+            &mut result.game, &mut result.game_jp,
+            &mut result.system, &mut result.system_jp,
+            &mut result.author, &mut result.author_jp,
+            &mut result.date,
+            &mut result.ripper,
+            &mut result.notes
+        ] {
+            *field = string_table.next().unwrap_or_default();
+        }
 
-        string_table.next().context("Missing release date!")?;
+        Ok(result)
+    }
 
-        result.ripper = String::from_utf8(string_table.next().context("Missing ripper name!")?.to_vec())?;
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let strings = [
+            &self.title, &self.title_jp,
+            &self.game, &self.game_jp,
+            &self.system, &self.system_jp,
+            &self.author, &self.author_jp,
+            &self.date,
+            &self.ripper,
+            &self.notes
+        ];
+        let string_table: Vec<u8> = strings
+            .iter()
+            .flat_map(|s| s.encode_utf16().chain([0]))
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+
+        let mut result = b"Gd3 ".to_vec();
+        result.extend(0x100u32.to_le_bytes());
+        result.extend((string_table.len() as u32).to_le_bytes());
+        result.extend(string_table);
+        result
+    }
 
-        string_table.next().context("Missing notes!")?;
+    /// Container metadata (title, artist, album, date, comment), using the Japanese fields where
+    /// preferred and available, and falling back to the other language otherwise
+    pub fn metadata(&self, prefer_japanese: bool) -> Vec<(&'static str, String)> {
+        let pick = |en: &String, jp: &String| -> String {
+            match (prefer_japanese && !jp.is_empty()) || en.is_empty() {
+                true => jp.trim().to_string(),
+                false => en.trim().to_string()
+            }
+        };
 
-        Ok(result)
+        [
+            ("title", pick(&self.title, &self.title_jp)),
+            ("artist", pick(&self.author, &self.author_jp)),
+            ("album", pick(&self.game, &self.game_jp)),
+            ("date", self.date.trim().to_string()),
+            ("comment", self.notes.trim().to_string())
+        ]
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .collect()
     }
 }
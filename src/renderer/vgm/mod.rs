@@ -1,15 +1,29 @@
 mod vgm;
 mod gd3;
 pub mod converter;
+pub mod recorder;
+pub mod player;
 
 pub use vgm::Vgm;
 
-pub fn duration_frames(vgm: &Vgm, engine_rate: u32, loops: usize) -> usize {
+const VGM_SAMPLE_RATE: f64 = 44100.0;
+
+/// Length in frames when played through Pegmode's driver, which waits in whole engine ticks
+pub fn duration_frames(vgm: &Vgm, engine_rate: u32, tma_offset: i32, loops: usize) -> usize {
     let loop_samples = vgm.loop_sample_count();
     let intro_samples = vgm.sample_count() - loop_samples;
 
-    let loop_frames = converter::samples_to_frames(loop_samples, engine_rate) as usize;
-    let intro_frames = converter::samples_to_frames(intro_samples, engine_rate) as usize;
+    let loop_ticks = converter::samples_to_frames(loop_samples, engine_rate) as u64;
+    let intro_ticks = converter::samples_to_frames(intro_samples, engine_rate) as u64;
+
+    let (tma, tac) = converter::engine_timer(engine_rate, tma_offset);
+    converter::engine_ticks_to_frames(intro_ticks + (loops as u64 * loop_ticks), tma, tac).round() as usize
+}
+
+/// Length in frames when the registers are written directly at their sample positions
+pub fn direct_duration_frames(vgm: &Vgm, frame_rate: f64, loops: usize) -> usize {
+    let loop_samples = vgm.loop_sample_count() as u64;
+    let intro_samples = vgm.sample_count() as u64 - loop_samples;
 
-    intro_frames + (loops * loop_frames)
+    ((intro_samples + loops as u64 * loop_samples) as f64 * frame_rate / VGM_SAMPLE_RATE).round() as usize
 }
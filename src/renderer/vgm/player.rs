@@ -0,0 +1,119 @@
+use anyhow::{Result, bail};
+use sameboy::Gameboy;
+use super::vgm::{Vgm, VgmIterItem};
+
+const VGM_SAMPLE_RATE: u64 = 44100;
+// One Game Boy frame (70224 clocks) in the 8MHz ticks returned by Gameboy::run
+const TICKS_PER_FRAME: u64 = 140448;
+
+struct RegisterWrite {
+    sample: u64,
+    addr: u16,
+    value: u8
+}
+
+#[derive(Default)]
+struct ConsoleStream {
+    writes: Vec<RegisterWrite>,
+    loop_index: Option<usize>,
+    cursor: usize,
+    ticks: u64,
+    target_ticks: u64,
+    sample_base: u64,
+    loop_count: u64,
+    ended: bool
+}
+
+impl ConsoleStream {
+    fn run_frame(&mut self, gb: &mut Gameboy, total_samples: u64, loop_start: Option<u64>) {
+        let ticks_per_second = 2 * gb.unmultiplied_clock_rate() as u64;
+
+        self.target_ticks += TICKS_PER_FRAME;
+        while self.ticks < self.target_ticks {
+            let sample = self.ticks * VGM_SAMPLE_RATE / ticks_per_second;
+
+            while let Some(write) = self.writes.get(self.cursor) {
+                if self.sample_base + write.sample > sample {
+                    break;
+                }
+                gb.write_memory(write.addr, write.value);
+                self.cursor += 1;
+            }
+
+            if !self.ended && sample >= self.sample_base + total_samples {
+                match (loop_start, self.loop_index) {
+                    (Some(loop_start), Some(loop_index)) => {
+                        self.sample_base += total_samples - loop_start;
+                        self.cursor = loop_index;
+                        self.loop_count += 1;
+                    },
+                    _ => self.ended = true
+                }
+            }
+
+            self.ticks += gb.run() as u64;
+        }
+    }
+}
+
+/// Plays a VGM by writing its registers straight into an otherwise idle console at their exact sample
+/// positions, instead of converting it to a GBS for the Pegmode driver
+pub struct VgmPlayer {
+    streams: [ConsoleStream; 2],
+    total_samples: u64,
+    loop_start: Option<u64>
+}
+
+impl VgmPlayer {
+    pub fn new(vgm: &Vgm) -> Result<Self> {
+        let mut streams: [ConsoleStream; 2] = Default::default();
+        let mut loop_start: Option<u64> = None;
+        let mut sample = 0u64;
+
+        for command in vgm.iter() {
+            match command {
+                VgmIterItem::HitLoopOffset => {
+                    loop_start = Some(sample);
+                    for stream in streams.iter_mut() {
+                        stream.loop_index = Some(stream.writes.len());
+                    }
+                },
+                VgmIterItem::WaitCommand(samples) => sample += samples as u64,
+                // Data blocks are either Deflemask sync writes, which only the patch ROM needs, or PCM for other chips
+                VgmIterItem::DataBlock(_, _) | VgmIterItem::OtherChipCommand(_) => (),
+                VgmIterItem::WriteLR35902RegCommand(is_2x, addr, value) => {
+                    streams[is_2x as usize].writes.push(RegisterWrite { sample, addr, value });
+                },
+                VgmIterItem::InvalidCommand(cmd) => {
+                    bail!("Invalid/unsupported VGM command {:02X}!", cmd);
+                }
+            }
+        }
+
+        Ok(Self {
+            streams,
+            total_samples: sample,
+            loop_start: loop_start.filter(|&loop_start| loop_start < sample)
+        })
+    }
+
+    pub fn run_frame(&mut self, gb: &mut Gameboy, gb_2x: Option<&mut Gameboy>) {
+        self.streams[0].run_frame(gb, self.total_samples, self.loop_start);
+        if let Some(gb_2x) = gb_2x {
+            self.streams[1].run_frame(gb_2x, self.total_samples, self.loop_start);
+        }
+    }
+
+    pub fn loop_count(&self) -> u64 {
+        self.streams[0].loop_count
+    }
+
+    /// Length of the looping part in frames, if there is one
+    pub fn loop_frames(&self, frame_rate: f64) -> Option<u64> {
+        self.loop_start.map(|loop_start| ((self.total_samples - loop_start) as f64 * frame_rate / VGM_SAMPLE_RATE as f64).round() as u64)
+    }
+
+    pub fn ended(&self) -> bool {
+        self.streams[0].ended
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::{Result, Context};
+use std::fs;
+use sameboy::{ApuChannel, ApuStateReceiver, MemoryInterceptor};
+use crate::renderer::sample_clock::SampleClock;
+use super::gd3::Gd3;
+
+const VGM_VERSION: u32 = 0x171;
+const VGM_HEADER_SIZE: usize = 0x100;
+const VGM_SAMPLE_RATE: u64 = 44100;
+const CLOCK_FLAG_IS_2X: u32 = 0x40000000;
+const CHIP_2X_FLAG: u8 = 0x80;
+
+const APU_REGISTERS_START: u16 = 0xFF10;
+const APU_REGISTERS_END: u16 = 0xFF3F;
+
+/// Records every APU register write of the main console(s) into a VGM file. Writes are timestamped
+/// with the sample count of the console that made them, which is tracked through the APU state stream.
+pub struct VgmRecorder {
+    output_path: String,
+    sample_rate: u64,
+    clock_rate: u32,
+    is_2x: bool,
+    gd3: Gd3,
+
+    clock: SampleClock,
+    track_start: u64,
+    // (output sample, register offset, value)
+    writes: Vec<(u64, u8, u8)>,
+    loop_samples: Option<(u64, u64)>
+}
+
+impl VgmRecorder {
+    pub fn new(output_path: &str, sample_rate: u32, clock_rate: u32, is_2x: bool) -> Self {
+        Self {
+            output_path: output_path.to_string(),
+            sample_rate: sample_rate as u64,
+            clock_rate,
+            is_2x,
+            gd3: Gd3 {
+                system: "Nintendo Game Boy".to_string(),
+                ..Default::default()
+            },
+            clock: SampleClock::default(),
+            track_start: 0,
+            writes: Vec::new(),
+            loop_samples: None
+        }
+    }
+
+    pub fn set_gd3(&mut self, title: &str, game: &str, author: &str, notes: &str) {
+        self.gd3.title = title.to_string();
+        self.gd3.game = game.to_string();
+        self.gd3.author = author.to_string();
+        self.gd3.notes = notes.to_string();
+    }
+
+    /// Mark the start of a new track, loop points are relative to it
+    pub fn start_track(&mut self) {
+        self.track_start = self.clock.samples();
+    }
+
+    /// Move every console forward in time without any register writes (e.g. the gaps between album tracks)
+    pub fn skip_samples(&mut self, samples: u64) {
+        self.clock.skip(samples);
+    }
+
+    /// Tag the first detected loop of the current track, in samples from the track's start
+    pub fn set_loop(&mut self, start: u64, length: u64) {
+        if self.loop_samples.is_none() {
+            self.loop_samples = Some((self.track_start + start, length));
+        }
+    }
+
+    fn to_vgm_samples(&self, samples: u64) -> u64 {
+        samples * VGM_SAMPLE_RATE / self.sample_rate
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let total_samples = self.to_vgm_samples(self.clock.samples());
+        let loop_samples = self.loop_samples
+            .map(|(start, length)| (self.to_vgm_samples(start), self.to_vgm_samples(length)))
+            .filter(|(start, length)| *length > 0 && start + length <= total_samples);
+
+        // A write of None marks the loop point, which goes before any writes on the same sample
+        let mut events: Vec<(u64, Option<(u8, u8)>)> = self.writes
+            .iter()
+            .map(|(sample, register, value)| (self.to_vgm_samples(*sample), Some((*register, *value))))
+            .collect();
+        if let Some((loop_start, _)) = loop_samples {
+            events.push((loop_start, None));
+        }
+        // Both consoles are stepped in turns, so their writes can be slightly out of order
+        events.sort_by_key(|(sample, write)| (*sample, write.is_some()));
+
+        let mut data: Vec<u8> = Vec::new();
+        let mut loop_offset: Option<usize> = None;
+        let mut position = 0u64;
+        for (sample, write) in events {
+            write_wait(&mut data, sample - position);
+            position = sample;
+
+            match write {
+                Some((register, value)) => data.extend([0xB3, register, value]),
+                None => loop_offset = Some(data.len())
+            }
+        }
+        write_wait(&mut data, total_samples.saturating_sub(position));
+        data.push(0x66);
+
+        let gd3 = self.gd3.to_bytes();
+
+        let mut header = vec![0u8; VGM_HEADER_SIZE];
+        let mut set_u32 = |offset: usize, value: u32| header[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        let file_size = VGM_HEADER_SIZE + data.len() + gd3.len();
+        set_u32(0x04, (file_size - 0x04) as u32);
+        set_u32(0x08, VGM_VERSION);
+        set_u32(0x14, (VGM_HEADER_SIZE + data.len() - 0x14) as u32);
+        set_u32(0x18, total_samples as u32);
+        if let (Some(offset), Some((_, length))) = (loop_offset, loop_samples) {
+            set_u32(0x1C, (VGM_HEADER_SIZE + offset - 0x1C) as u32);
+            set_u32(0x20, length as u32);
+        }
+        set_u32(0x34, (VGM_HEADER_SIZE - 0x34) as u32);
+        set_u32(0x80, self.clock_rate | if self.is_2x { CLOCK_FLAG_IS_2X } else { 0 });
+        header[..4].copy_from_slice(b"Vgm ");
+
+        let mut result = header;
+        result.extend(data);
+        result.extend(gd3);
+
+        fs::write(&self.output_path, result)
+            .with_context(|| format!("Failed to write VGM file {}", self.output_path))
+    }
+}
+
+fn write_wait(data: &mut Vec<u8>, samples: u64) {
+    let mut remaining = samples;
+    while remaining > 0 {
+        match remaining {
+            735 => {
+                data.push(0x62);
+                remaining = 0;
+            },
+            882 => {
+                data.push(0x63);
+                remaining = 0;
+            },
+            1..=16 => {
+                data.push(0x70 + (remaining - 1) as u8);
+                remaining = 0;
+            },
+            _ => {
+                let wait = remaining.min(u16::MAX as u64);
+                data.push(0x61);
+                data.extend((wait as u16).to_le_bytes());
+                remaining -= wait;
+            }
+        }
+    }
+}
+
+impl MemoryInterceptor for VgmRecorder {
+    fn intercept_write(&mut self, id: usize, addr: u16, data: u8) -> bool {
+        if (APU_REGISTERS_START..=APU_REGISTERS_END).contains(&addr) {
+            let samples = self.clock.console_samples(id);
+            let register = (addr - APU_REGISTERS_START) as u8 | if id == 1 { CHIP_2X_FLAG } else { 0 };
+            self.writes.push((samples, register, data));
+        }
+        true
+    }
+}
+
+impl ApuStateReceiver for VgmRecorder {
+    fn receive(&mut self, id: usize, channel: ApuChannel, _volume: u8, _amplitude: u8, _frequency: f64, _timbre: usize, _balance: f64, _edge: bool) {
+        self.clock.receive(id, channel);
+    }
+}
@@ -11,9 +11,45 @@ pub enum VgmIterItem {
     WaitCommand(u16),
     DataBlock(u8, Vec<u8>),
     WriteLR35902RegCommand(bool, u16, u8),
+    /// A command for another chip (or a PCM RAM write), skipped using the command length table
+    OtherChipCommand(u8),
     InvalidCommand(u8)
 }
 
+// Header offsets of every chip clock, in the order of the VGM spec
+const CHIP_CLOCKS: [(&str, usize); 42] = [
+    ("SN76489", 0x0C), ("YM2413", 0x10), ("YM2612", 0x2C), ("YM2151", 0x30), ("SegaPCM", 0x38),
+    ("RF5C68", 0x40), ("YM2203", 0x44), ("YM2608", 0x48), ("YM2610", 0x4C), ("YM3812", 0x50),
+    ("YM3526", 0x54), ("Y8950", 0x58), ("YMF262", 0x5C), ("YMF278B", 0x60), ("YMF271", 0x64),
+    ("YMZ280B", 0x68), ("RF5C164", 0x6C), ("PWM", 0x70), ("AY8910", 0x74), ("Game Boy", 0x80),
+    ("NES APU", 0x84), ("MultiPCM", 0x88), ("uPD7759", 0x8C), ("OKIM6258", 0x90), ("OKIM6295", 0x98),
+    ("K051649", 0x9C), ("K054539", 0xA0), ("HuC6280", 0xA4), ("C140", 0xA8), ("K053260", 0xAC),
+    ("Pokey", 0xB0), ("QSound", 0xB4), ("SCSP", 0xB8), ("WonderSwan", 0xC0), ("VSU", 0xC4),
+    ("SAA1099", 0xC8), ("ES5503", 0xCC), ("ES5506", 0xD0), ("X1-010", 0xD8), ("C352", 0xDC),
+    ("GA20", 0xE0), ("Mikey", 0xE4)
+];
+
+/// Operand length of every command that isn't handled explicitly, per the VGM spec
+/// (reserved ranges included, so files from newer spec versions can still be skipped through)
+fn command_length(command: u8) -> Option<usize> {
+    match command {
+        0x30..=0x3F | 0x4F | 0x50 => Some(1),
+        0x40..=0x4E | 0x51..=0x5F | 0xA0..=0xBF => Some(2),
+        0x64 | 0xC0..=0xDF => Some(3),
+        0xE0..=0xFF => Some(4),
+        // PCM RAM write
+        0x68 => Some(11),
+        // YM2612 DAC write from the data bank
+        0x80..=0x8F => Some(0),
+        // DAC stream control
+        0x90 | 0x91 | 0x95 => Some(4),
+        0x92 => Some(5),
+        0x93 => Some(10),
+        0x94 => Some(1),
+        _ => None
+    }
+}
+
 pub struct Vgm {
     data: Vec<u8>
 }
@@ -35,14 +71,12 @@ macro_rules! u32_fn {
 }
 
 const CLOCK_FLAG_IS_2X: u32 = 0x40000000;
+const CLOCK_FLAG_ALT_MODE: u32 = 0x80000000;
 
 macro_rules! clock_fn {
     ($name: tt, $offset: literal) => {
         pub fn $name(&self) -> Option<(u32, bool)> {
-            match self.read_u32($offset) {
-                0 => None,
-                value => Some((value & !CLOCK_FLAG_IS_2X, (value & CLOCK_FLAG_IS_2X) != 0))
-            }
+            self.read_clock($offset)
         }
     }
 }
@@ -50,19 +84,38 @@ macro_rules! clock_fn {
 impl Vgm {
     pub fn new(data: &[u8]) -> Result<Self> {
         let mut result = Self { data: Vec::new() };
-        if &data[0..4] == b"Vgm " {
+        if data.starts_with(b"Vgm ") {
             result.data.extend_from_slice(data);
         } else {
             let mut decoder = GzDecoder::new(data);
             ensure!(decoder.header().is_some(), "Input data is not a valid VGM or VGZ");
             decoder.read_to_end(&mut result.data).context("VGZ inflate failed")?;
-            ensure!(&result.data[0..4] == b"Vgm ", "Input data is a valid gzip file but not a VGZ");
+            ensure!(result.data.starts_with(b"Vgm "), "Input data is a valid gzip file but not a VGZ");
         };
-        ensure!(result.version() >= 0x161, "VGM version not supported");
+        ensure!(result.data.len() >= 0x40, "VGM header is truncated");
+        // The Game Boy clock was only added to the header in 1.61
         ensure!(result.lr35902_clock().is_some(), "VGM does not contain a Game Boy");
+
+        let other_chips: Vec<String> = result.chips()
+            .into_iter()
+            .filter(|(name, _, _)| *name != "Game Boy")
+            .map(|(name, _, is_2x)| if is_2x { format!("2x {}", name) } else { name.to_string() })
+            .collect();
+        if !other_chips.is_empty() {
+            println!("VGM also contains {}, only the Game Boy part will be rendered", other_chips.join(", "));
+        }
+
         Ok(result)
     }
 
+    /// Every chip with a clock in the header, as (name, clock, is 2x)
+    pub fn chips(&self) -> Vec<(&'static str, u32, bool)> {
+        CHIP_CLOCKS
+            .iter()
+            .filter_map(|(name, offset)| self.read_clock(*offset).map(|(clock, is_2x)| (*name, clock, is_2x)))
+            .collect()
+    }
+
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         let data = fs::read(path)?;
         Self::new(&data)
@@ -74,10 +127,9 @@ impl Vgm {
             return None;
         }
 
-        if let Ok(gd3) = Gd3::new(&self.data[gd3_offset..]) {
-            Some(gd3)
-        } else {
-            None
+        match self.data.get(gd3_offset..).map(Gd3::new) {
+            Some(Ok(gd3)) => Some(gd3),
+            _ => None
         }
     }
 
@@ -92,16 +144,38 @@ impl Vgm {
         u32::from_le_bytes(data)
     }
 
+    /// Header fields past the start of the data (in older or shorter headers) read as 0
+    fn read_header_u32(&self, offset: usize) -> u32 {
+        match offset + 4 <= self.start_offset().min(self.data.len()) {
+            true => self.read_u32(offset),
+            false => 0
+        }
+    }
+
     fn read_relative_offset(&self, offset: usize) -> usize {
         (self.read_u32(offset) as usize) + offset
     }
 
+    fn read_clock(&self, offset: usize) -> Option<(u32, bool)> {
+        match self.read_header_u32(offset) & !CLOCK_FLAG_ALT_MODE {
+            0 => None,
+            value => Some((value & !CLOCK_FLAG_IS_2X, (value & CLOCK_FLAG_IS_2X) != 0))
+        }
+    }
+
+    pub fn start_offset(&self) -> usize {
+        // Versions before 1.50 always start the data right after the 0x40 byte header
+        match self.version() >= 0x150 && self.read_u32(0x34) != 0 {
+            true => self.read_relative_offset(0x34),
+            false => 0x40
+        }
+    }
+
     relative_offset_fn!(end_offset, 0x4);
     u32_fn!(version, 0x8);
     u32_fn!(sample_count, 0x18);
     relative_offset_fn!(loop_offset, 0x1C);
     u32_fn!(loop_sample_count, 0x20);
-    relative_offset_fn!(start_offset, 0x34);
     clock_fn!(lr35902_clock, 0x80);
 
     pub fn iter(&self) -> VgmIterator<'_> {
@@ -112,6 +186,7 @@ impl Vgm {
 pub struct VgmIterator<'a> {
     vgm: &'a Vgm,
     iter_ptr: usize,
+    end_offset: usize,
     hit_loop_offset: bool,
     hit_invalid_command: bool
 }
@@ -121,6 +196,7 @@ impl<'a> VgmIterator<'a> {
         Self {
             vgm,
             iter_ptr: vgm.start_offset(),
+            end_offset: vgm.end_offset().min(vgm.data.len()),
             hit_loop_offset: false,
             hit_invalid_command: false
         }
@@ -139,13 +215,17 @@ impl<'a> VgmIterator<'a> {
     fn iter_u32(&mut self) -> u32 {
         u32::from_le_bytes([self.iter_u8(), self.iter_u8(), self.iter_u8(), self.iter_u8()])
     }
+
+    fn operands_available(&self, length: usize) -> bool {
+        self.iter_ptr + length <= self.end_offset
+    }
 }
 
 impl Iterator for VgmIterator<'_> {
     type Item = VgmIterItem;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.iter_ptr >= self.vgm.end_offset() || self.hit_invalid_command {
+        if self.iter_ptr >= self.end_offset || self.hit_invalid_command {
             return None;
         }
         if !self.hit_loop_offset && self.iter_ptr == self.vgm.loop_offset() {
@@ -154,6 +234,17 @@ impl Iterator for VgmIterator<'_> {
         }
 
         let command = self.iter_u8();
+        let operand_length = match command {
+            0x61 | 0xB3 => 2,
+            0x67 => 6,
+            command => command_length(command).unwrap_or_default()
+        };
+        if !self.operands_available(operand_length) {
+            // Truncated file
+            self.hit_invalid_command = true;
+            return None;
+        }
+
         match command {
             // Wait commands
             0x61 => Some(VgmIterItem::WaitCommand(self.iter_u16())),
@@ -163,15 +254,21 @@ impl Iterator for VgmIterator<'_> {
                 let wait = (command & 0xF) + 1;
                 Some(VgmIterItem::WaitCommand(wait as u16))
             }
+            // YM2612 DAC write + wait
+            0x81..=0x8F => Some(VgmIterItem::WaitCommand((command & 0xF) as u16)),
             // Data block
             0x67 => {
                 let _compat_eof_cmd = self.iter_u8();
                 let block_type = self.iter_u8();
-                let block_size = self.iter_u32() as usize;
+                // The top bit marks blocks for the second chip
+                let block_size = (self.iter_u32() & 0x7FFFFFFF) as usize;
+                if !self.operands_available(block_size) {
+                    self.hit_invalid_command = true;
+                    return None;
+                }
 
-                let block: Vec<u8> = (0..block_size)
-                    .map(|_| self.iter_u8())
-                    .collect();
+                let block = self.vgm.data[self.iter_ptr..(self.iter_ptr + block_size)].to_vec();
+                self.iter_ptr += block_size;
                 Some(VgmIterItem::DataBlock(block_type, block))
             }
             // LR35902 register write
@@ -182,10 +279,15 @@ impl Iterator for VgmIterator<'_> {
             }
             // EOF
             0x66 => None,
-            // Invalid
-            invalid => {
-                self.hit_invalid_command = true;
-                Some(VgmIterItem::InvalidCommand(invalid))
+            command => match command_length(command) {
+                Some(length) => {
+                    self.iter_ptr += length;
+                    Some(VgmIterItem::OtherChipCommand(command))
+                },
+                None => {
+                    self.hit_invalid_command = true;
+                    Some(VgmIterItem::InvalidCommand(command))
+                }
             }
         }
     }
@@ -0,0 +1,256 @@
+use anyhow::{Result, Context};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use ffmpeg_next::{format, encoder, codec, media, ChannelLayout, Dictionary, Packet, Rational, software, frame};
+use super::as_u8_slice;
+use super::vb_unwrap::VideoBuilderUnwrap;
+use super::ffmpeg_hacks::{ffmpeg_copy_codec_params, ffmpeg_copy_context_params, ffmpeg_create_context, ffmpeg_get_audio_context_frame_size, ffmpeg_context_bytes_written};
+
+const INPUT_FORMAT: format::Sample = format::Sample::I16(format::sample::Type::Packed);
+
+/// Writes interleaved s16 audio to an audio-only container, using the container's default codec
+/// (e.g. PCM for .wav, FLAC for .flac, Opus for .opus)
+pub struct AudioBuilder {
+    out_ctx: format::context::Output,
+
+    encoder: encoder::Audio,
+    /// Converts to the encoder's sample rate, if it doesn't support the input one (e.g. Opus)
+    rate_ctx: Option<software::resampling::Context>,
+    swr_ctx: software::resampling::Context,
+    stream_idx: usize,
+    frame_size: usize,
+    channels: usize,
+    time_base: Rational,
+    sample_buf: VecDeque<i16>,
+    pts: i64
+}
+
+impl AudioBuilder {
+    pub fn new(output_path: &str, sample_rate: i32, channels: i32, metadata: &HashMap<String, String>) -> Result<Self> {
+        let mut out_ctx = format::output(&output_path).vb_unwrap()?;
+
+        let mut out_metadata = Dictionary::new();
+        for (k, v) in metadata.iter() {
+            out_metadata.set(k.as_str(), v.as_str());
+        }
+        out_ctx.set_metadata(out_metadata);
+
+        let global_header = out_ctx.format().flags().contains(format::Flags::GLOBAL_HEADER);
+        let codec_id = out_ctx.format().codec(&output_path, media::Type::Audio);
+        // FFmpeg's native Opus encoder is experimental, so prefer libopus when it's there
+        let codec = match codec_id {
+            codec::Id::OPUS => encoder::find_by_name("libopus").or_else(|| encoder::find(codec_id)),
+            _ => encoder::find(codec_id)
+        }.with_context(|| format!("No audio encoder available for {}", output_path))?;
+        let audio_codec = codec.audio().vb_unwrap()?;
+        let sample_format = audio_codec
+            .formats()
+            .and_then(|mut formats| formats.next())
+            .unwrap_or(INPUT_FORMAT);
+        let output_rate = match audio_codec.rates() {
+            Some(rates) => {
+                let rates: Vec<i32> = rates.collect();
+                match rates.contains(&sample_rate) {
+                    true => sample_rate,
+                    // The closest rate that doesn't lose anything, or the highest one available
+                    false => rates.iter().filter(|&&rate| rate >= sample_rate).min().or(rates.iter().max()).cloned().unwrap_or(sample_rate)
+                }
+            },
+            None => sample_rate
+        };
+        let channel_layout = ChannelLayout::default(channels);
+        let time_base: Rational = (1, output_rate).into();
+
+        let mut stream = out_ctx.add_stream(codec).vb_unwrap()?;
+        let mut context = ffmpeg_create_context(codec, stream.parameters())?
+            .encoder()
+            .audio()
+            .vb_unwrap()?;
+
+        context.set_rate(output_rate);
+        context.set_format(sample_format);
+        context.set_channels(channels);
+        context.set_channel_layout(channel_layout);
+        context.set_time_base(time_base);
+        if global_header {
+            context.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        ffmpeg_copy_codec_params(&mut stream, &context, &codec)?;
+
+        stream.set_time_base(time_base);
+
+        let mut context_options = Dictionary::new();
+        // Needed for FFmpeg's own Opus encoder if libopus isn't available
+        context_options.set("strict", "experimental");
+        let encoder = context.open_as_with(codec, context_options).vb_unwrap()?;
+        let stream_idx = stream.index();
+        // Only known once the encoder is open (e.g. FLAC picks its block size from the sample rate)
+        let frame_size = ffmpeg_get_audio_context_frame_size(encoder.as_ref(), 1024);
+
+        ffmpeg_copy_context_params(&mut stream, encoder.as_ref())?;
+
+        let rate_ctx = match output_rate == sample_rate {
+            true => None,
+            false => Some(software::resampler(
+                (INPUT_FORMAT, channel_layout, sample_rate as u32),
+                (INPUT_FORMAT, channel_layout, output_rate as u32)
+            ).vb_unwrap()?)
+        };
+        let swr_ctx = software::resampler(
+            (INPUT_FORMAT, channel_layout, output_rate as u32),
+            (sample_format, channel_layout, output_rate as u32)
+        ).vb_unwrap()?;
+
+        Ok(Self {
+            out_ctx,
+            encoder,
+            rate_ctx,
+            swr_ctx,
+            stream_idx,
+            frame_size,
+            channels: channels as usize,
+            time_base,
+            sample_buf: VecDeque::new(),
+            pts: 0
+        })
+    }
+
+    pub fn start_encoding(&mut self) -> Result<()> {
+        self.out_ctx.write_header().vb_unwrap()?;
+
+        Ok(())
+    }
+
+    pub fn push_audio_data(&mut self, audio: &[i16]) -> Result<()> {
+        match self.rate_ctx.is_some() {
+            true => self.resample(Some(audio))?,
+            false => self.sample_buf.extend(audio)
+        }
+
+        while self.sample_buf.len() >= self.frame_size * self.channels {
+            self.encode_frame(self.frame_size)?;
+        }
+
+        Ok(())
+    }
+
+    /// Convert to the output sample rate into `sample_buf`, flushing the resampler when `audio` is None
+    fn resample(&mut self, audio: Option<&[i16]>) -> Result<()> {
+        let rate_ctx = match &mut self.rate_ctx {
+            Some(rate_ctx) => rate_ctx,
+            None => return Ok(())
+        };
+        let (input_rate, output_rate) = (rate_ctx.input().rate as usize, rate_ctx.output().rate as usize);
+        let channel_layout = rate_ctx.input().channel_layout;
+
+        let input_samples = audio.map_or(0, |audio| audio.len() / self.channels);
+        // Room for everything the resampler might still hold on to as well
+        let capacity = (input_samples + 256) * output_rate / input_rate + 64;
+        let mut output_frame = frame::Audio::new(INPUT_FORMAT, capacity, channel_layout);
+        output_frame.set_rate(output_rate as u32);
+
+        match audio {
+            Some(audio) => {
+                let mut input_frame = frame::Audio::new(INPUT_FORMAT, input_samples, channel_layout);
+                input_frame.set_rate(input_rate as u32);
+                let audio = as_u8_slice(audio);
+                input_frame.data_mut(0)[..audio.len()].copy_from_slice(audio);
+                rate_ctx.run(&input_frame, &mut output_frame).vb_unwrap()?;
+            },
+            None => {
+                rate_ctx.flush(&mut output_frame).vb_unwrap()?;
+            }
+        }
+
+        let bytes = output_frame.samples() * self.channels * INPUT_FORMAT.bytes();
+        self.sample_buf.extend(
+            output_frame.data(0)[..bytes]
+                .chunks_exact(2)
+                .map(|b| i16::from_ne_bytes([b[0], b[1]]))
+        );
+
+        Ok(())
+    }
+
+    fn encode_frame(&mut self, samples: usize) -> Result<()> {
+        let mut input_frame = frame::Audio::new(INPUT_FORMAT, samples, self.swr_ctx.input().channel_layout);
+        input_frame.set_rate(self.swr_ctx.input().rate);
+        let audio: Vec<i16> = self.sample_buf.drain(0..(samples * self.channels)).collect();
+        let audio = as_u8_slice(&audio);
+        input_frame.data_mut(0)[..audio.len()].copy_from_slice(audio);
+
+        let mut output_frame = frame::Audio::new(self.swr_ctx.output().format, samples, self.swr_ctx.output().channel_layout);
+        output_frame.set_rate(self.swr_ctx.output().rate);
+        self.swr_ctx.run(&input_frame, &mut output_frame).vb_unwrap()?;
+
+        output_frame.set_pts(Some(self.pts));
+        self.encoder.send_frame(&output_frame).vb_unwrap()?;
+        self.pts += samples as i64;
+
+        self.mux_packets()
+    }
+
+    fn mux_packets(&mut self) -> Result<()> {
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            let out_time_base = self.out_ctx.stream(self.stream_idx)
+                .unwrap()
+                .time_base();
+
+            packet.rescale_ts(self.time_base, out_time_base);
+            packet.set_stream(self.stream_idx);
+            packet.write_interleaved(&mut self.out_ctx).vb_unwrap()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish_encoding(&mut self) -> Result<()> {
+        self.resample(None)?;
+        while self.sample_buf.len() >= self.frame_size * self.channels {
+            self.encode_frame(self.frame_size)?;
+        }
+
+        let remaining = self.sample_buf.len() / self.channels;
+        if remaining > 0 {
+            let capabilities = self.encoder.codec().unwrap().capabilities();
+            if !capabilities.intersects(codec::Capabilities::SMALL_LAST_FRAME | codec::Capabilities::VARIABLE_FRAME_SIZE) {
+                self.sample_buf.resize(self.frame_size * self.channels, 0);
+                self.encode_frame(self.frame_size)?;
+            } else {
+                self.encode_frame(remaining)?;
+            }
+        }
+
+        self.encoder.send_eof().vb_unwrap()?;
+        self.mux_packets()?;
+
+        self.out_ctx.write_trailer().vb_unwrap()?;
+
+        Ok(())
+    }
+
+    /// Chapters have to be added before `start_encoding`: the MOV/MP4 muxer only creates its chapter
+    /// track if there are chapters when the header is written
+    pub fn add_chapter(&mut self, title: &str, start: Duration, end: Duration) -> Result<()> {
+        let id = self.out_ctx.nb_chapters() as i64;
+        self.out_ctx
+            .add_chapter(id, (1, 1000), start.as_millis() as i64, end.as_millis() as i64, title)
+            .vb_unwrap()?;
+
+        Ok(())
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    pub fn encoded_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.pts as f64 * self.time_base.numerator() as f64 / self.time_base.denominator() as f64)
+    }
+
+    pub fn encoded_size(&self) -> usize {
+        ffmpeg_context_bytes_written(&self.out_ctx)
+    }
+}
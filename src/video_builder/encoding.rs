@@ -110,6 +110,44 @@ impl VideoBuilder {
         Ok(())
     }
 
+    /// Stems aren't part of the audio/video interleaving in `step_encoding`, so they're encoded and
+    /// muxed right away
+    pub fn push_stem_audio_data(&mut self, index: usize, audio: &[u8]) -> Result<()> {
+        let bytes_per_sample = self.a_swr_ctx.input().channel_layout.channels() as usize * self.a_swr_ctx.input().format.bytes();
+        let samples = audio.len() / bytes_per_sample;
+        let stem = &mut self.a_stems[index];
+
+        let mut input_frame = frame::Audio::new(stem.swr_ctx.input().format, samples, stem.swr_ctx.input().channel_layout);
+        input_frame.set_rate(self.options.sample_rate as _);
+        input_frame.data_mut(0)[..audio.len()].copy_from_slice(audio);
+
+        let mut output_frame = frame::Audio::new(stem.swr_ctx.output().format, samples, stem.swr_ctx.output().channel_layout);
+        output_frame.set_rate(self.options.sample_rate as _);
+        stem.swr_ctx.run(&input_frame, &mut output_frame).vb_unwrap()?;
+
+        output_frame.set_pts(Some(stem.pts));
+        stem.encoder.send_frame(&output_frame).vb_unwrap()?;
+        stem.pts += samples as i64;
+
+        self.mux_stem_packets(index)
+    }
+
+    fn mux_stem_packets(&mut self, index: usize) -> Result<()> {
+        let stem = &mut self.a_stems[index];
+        let out_time_base = self.out_ctx.stream(stem.stream_idx)
+            .unwrap()
+            .time_base();
+
+        let mut packet = Packet::empty();
+        while stem.encoder.receive_packet(&mut packet).is_ok() {
+            packet.rescale_ts(self.options.audio_time_base, out_time_base);
+            packet.set_stream(stem.stream_idx);
+            packet.write_interleaved(&mut self.out_ctx).vb_unwrap()?;
+        }
+
+        Ok(())
+    }
+
     fn send_video_to_encoder(&mut self) -> Result<()> {
         if let Some(mut frame) = self.v_frame_buf.pop_front() {
             frame.set_pts(Some(self.v_pts));
@@ -217,11 +255,27 @@ impl VideoBuilder {
             }
         }
 
+        for index in 0..self.a_stems.len() {
+            self.a_stems[index].encoder.send_eof().vb_unwrap()?;
+            self.mux_stem_packets(index)?;
+        }
+
         self.out_ctx.write_trailer().vb_unwrap()?;
 
         Ok(())
     }
 
+    /// Chapters have to be added before `start_encoding`: the MOV/MP4 muxer only creates its chapter
+    /// track if there are chapters when the header is written
+    pub fn add_chapter(&mut self, title: &str, start: Duration, end: Duration) -> Result<()> {
+        let id = self.out_ctx.nb_chapters() as i64;
+        self.out_ctx
+            .add_chapter(id, (1, 1000), start.as_millis() as i64, end.as_millis() as i64, title)
+            .vb_unwrap()?;
+
+        Ok(())
+    }
+
     pub fn audio_frame_size(&self) -> usize {
         self.a_frame_size
     }
@@ -1,7 +1,7 @@
 use anyhow::{Result, anyhow};
 use std::ffi::{CStr, CString};
 use ffmpeg_next::{codec, Codec, Error, format, StreamMut};
-use ffmpeg_sys_next::{av_get_sample_fmt, avcodec_alloc_context3, avcodec_parameters_from_context, avcodec_parameters_to_context, av_version_info};
+use ffmpeg_sys_next::{AV_DISPOSITION_DEFAULT, av_get_sample_fmt, avcodec_alloc_context3, avcodec_parameters_from_context, avcodec_parameters_to_context, av_version_info};
 
 pub fn ffmpeg_version() -> &'static str {
     // ffmpeg-next does not provide a way to get the FFmpeg version number. It does provide the
@@ -62,6 +62,20 @@ pub fn ffmpeg_copy_codec_params(stream: &mut StreamMut, context: &codec::Context
     Ok(())
 }
 
+pub fn ffmpeg_set_stream_default(stream: &mut StreamMut, default: bool) {
+    // ffmpeg-next does not provide a setter for stream dispositions.
+    // Safety: All mutable pointer dereferences are done strictly on initialized memory since they
+    //         come from a mutable reference to a safe abstraction.
+    unsafe {
+        let disposition = &mut (*stream.as_mut_ptr()).disposition;
+        if default {
+            *disposition |= AV_DISPOSITION_DEFAULT as i32;
+        } else {
+            *disposition &= !(AV_DISPOSITION_DEFAULT as i32);
+        }
+    }
+}
+
 pub fn ffmpeg_sample_format_from_string(value: &str) -> format::Sample {
     // This is provided by ffmpeg-next, but only for `&'static str`, presumably due to
     // some confusion over the `const char*` in the method signature?
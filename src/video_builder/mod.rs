@@ -2,17 +2,20 @@ pub mod video_options;
 mod vb_unwrap;
 mod ffmpeg_hacks;
 mod encoding;
+pub mod audio_builder;
 pub mod backgrounds;
 
 use anyhow::{Result, Context};
 use std::collections::VecDeque;
 use std::{mem, slice};
 use std::str::FromStr;
+use std::time::Duration;
 use ffmpeg_next::{self, format, encoder, codec, ChannelLayout, Dictionary, software, frame};
 use video_options::VideoOptions;
+use audio_builder::AudioBuilder;
 use vb_unwrap::VideoBuilderUnwrap;
 use crate::video_builder::backgrounds::{get_video_background, VideoBackground};
-use crate::video_builder::ffmpeg_hacks::{ffmpeg_copy_codec_params, ffmpeg_copy_context_params, ffmpeg_create_context, ffmpeg_sample_format_from_string, ffmpeg_get_audio_context_frame_size};
+use crate::video_builder::ffmpeg_hacks::{ffmpeg_copy_codec_params, ffmpeg_copy_context_params, ffmpeg_create_context, ffmpeg_sample_format_from_string, ffmpeg_get_audio_context_frame_size, ffmpeg_set_stream_default};
 pub use ffmpeg_hacks::ffmpeg_version;
 
 pub fn init() -> Result<()> {
@@ -28,6 +31,126 @@ pub fn as_u8_slice<T: Sized>(s: &[T]) -> &[u8] {
     }
 }
 
+const NULL_FRAME_SIZE: usize = 1024;
+
+/// Either a full video encode, or an audio-only one when `VideoOptions::audio_only` is set.
+/// `Null` discards everything, for passes that only analyze the audio.
+pub enum OutputBuilder {
+    Video(VideoBuilder),
+    Audio(AudioBuilder),
+    Null { sample_rate: i32, channels: i32, samples: usize }
+}
+
+impl OutputBuilder {
+    pub fn new(options: VideoOptions) -> Result<Self> {
+        if options.audio_only {
+            let ab = AudioBuilder::new(&options.output_path, options.sample_rate, options.audio_channels, &options.metadata)?;
+            Ok(Self::Audio(ab))
+        } else {
+            Ok(Self::Video(VideoBuilder::new(options)?))
+        }
+    }
+
+    pub fn null(options: &VideoOptions) -> Self {
+        Self::Null {
+            sample_rate: options.sample_rate,
+            channels: options.audio_channels,
+            samples: 0
+        }
+    }
+
+    pub fn has_video(&self) -> bool {
+        matches!(self, Self::Video(_))
+    }
+
+    pub fn start_encoding(&mut self) -> Result<()> {
+        match self {
+            Self::Video(vb) => vb.start_encoding(),
+            Self::Audio(ab) => ab.start_encoding(),
+            Self::Null { .. } => Ok(())
+        }
+    }
+
+    pub fn push_video_data(&mut self, video: &[u8]) -> Result<()> {
+        match self {
+            Self::Video(vb) => vb.push_video_data(video),
+            Self::Audio(_) | Self::Null { .. } => Ok(())
+        }
+    }
+
+    pub fn push_audio_data(&mut self, audio: &[i16]) -> Result<()> {
+        match self {
+            Self::Video(vb) => vb.push_audio_data(as_u8_slice(audio)),
+            Self::Audio(ab) => ab.push_audio_data(audio),
+            Self::Null { channels, samples, .. } => {
+                *samples += audio.len() / *channels as usize;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn push_stem_audio_data(&mut self, index: usize, audio: &[i16]) -> Result<()> {
+        match self {
+            Self::Video(vb) => vb.push_stem_audio_data(index, as_u8_slice(audio)),
+            Self::Audio(_) | Self::Null { .. } => Ok(())
+        }
+    }
+
+    pub fn step_encoding(&mut self) -> Result<()> {
+        match self {
+            Self::Video(vb) => vb.step_encoding(),
+            Self::Audio(_) | Self::Null { .. } => Ok(())
+        }
+    }
+
+    pub fn finish_encoding(&mut self) -> Result<()> {
+        match self {
+            Self::Video(vb) => vb.finish_encoding(),
+            Self::Audio(ab) => ab.finish_encoding(),
+            Self::Null { .. } => Ok(())
+        }
+    }
+
+    pub fn add_chapter(&mut self, title: &str, start: Duration, end: Duration) -> Result<()> {
+        match self {
+            Self::Video(vb) => vb.add_chapter(title, start, end),
+            Self::Audio(ab) => ab.add_chapter(title, start, end),
+            Self::Null { .. } => Ok(())
+        }
+    }
+
+    pub fn audio_frame_size(&self) -> usize {
+        match self {
+            Self::Video(vb) => vb.audio_frame_size(),
+            Self::Audio(ab) => ab.frame_size(),
+            Self::Null { .. } => NULL_FRAME_SIZE
+        }
+    }
+
+    pub fn encoded_duration(&self) -> Duration {
+        match self {
+            Self::Video(vb) => vb.encoded_video_duration(),
+            Self::Audio(ab) => ab.encoded_duration(),
+            Self::Null { sample_rate, samples, .. } => Duration::from_secs_f64(*samples as f64 / *sample_rate as f64)
+        }
+    }
+
+    pub fn encoded_size(&self) -> usize {
+        match self {
+            Self::Video(vb) => vb.encoded_video_size(),
+            Self::Audio(ab) => ab.encoded_size(),
+            Self::Null { .. } => 0
+        }
+    }
+}
+
+struct StemStream {
+    encoder: encoder::Audio,
+    swr_ctx: software::resampling::Context,
+    stream_idx: usize,
+    pts: i64
+}
+
 pub struct VideoBuilder {
     options: VideoOptions,
 
@@ -49,7 +172,9 @@ pub struct VideoBuilder {
     a_stream_idx: usize,
     a_frame_size: usize,
     a_pts: i64,
-    a_pts_muxed: i64
+    a_pts_muxed: i64,
+
+    a_stems: Vec<StemStream>
 }
 
 impl VideoBuilder {
@@ -127,6 +252,22 @@ impl VideoBuilder {
         let (v_encoder, v_stream_idx) = Self::create_video_encoder(options.clone(), &mut out_ctx)?;
         let (a_encoder, a_stream_idx, a_frame_size) = Self::create_audio_encoder(options.clone(), &mut out_ctx)?;
 
+        let mut a_stems: Vec<StemStream> = Vec::with_capacity(options.audio_stem_titles.len());
+        if !options.audio_stem_titles.is_empty() {
+            Self::set_audio_stream_title(&mut out_ctx, a_stream_idx, "Mix", true);
+        }
+        for title in options.audio_stem_titles.iter() {
+            let (encoder, stream_idx, _) = Self::create_audio_encoder(options.clone(), &mut out_ctx)?;
+            Self::set_audio_stream_title(&mut out_ctx, stream_idx, title, false);
+
+            a_stems.push(StemStream {
+                encoder,
+                swr_ctx: software::resampler(swr_in, swr_out).vb_unwrap()?,
+                stream_idx,
+                pts: 0
+            });
+        }
+
         Ok(Self {
             options,
             background,
@@ -144,10 +285,20 @@ impl VideoBuilder {
             a_stream_idx,
             a_frame_size,
             a_pts: 0,
-            a_pts_muxed: 0
+            a_pts_muxed: 0,
+            a_stems
         })
     }
 
+    fn set_audio_stream_title(out_ctx: &mut format::context::Output, stream_idx: usize, title: &str, default: bool) {
+        let mut stream = out_ctx.stream_mut(stream_idx).unwrap();
+
+        let mut metadata = Dictionary::new();
+        metadata.set("title", title);
+        stream.set_metadata(metadata);
+        ffmpeg_set_stream_default(&mut stream, default);
+    }
+
     fn create_video_encoder(options: VideoOptions, out_ctx: &mut format::context::Output) -> Result<(encoder::Video, usize)> {
         let global_header = out_ctx.format().flags().contains(format::Flags::GLOBAL_HEADER);
         let output_format = format::Pixel::from_str(&options.pixel_format_out).vb_unwrap()?;
@@ -1,9 +1,19 @@
 use std::collections::HashMap;
 use ffmpeg_next::Rational;
 
+/// Output extensions that get an audio-only render
+pub const AUDIO_ONLY_EXTENSIONS: [&str; 3] = ["wav", "flac", "opus"];
+
+pub fn is_audio_only_path(path: &str) -> bool {
+    let path = path.to_lowercase();
+    AUDIO_ONLY_EXTENSIONS.iter().any(|extension| path.ends_with(&format!(".{}", extension)))
+}
+
 #[derive(Clone)]
 pub struct VideoOptions {
     pub output_path: String,
+    /// Skip the video stream and write only audio, using the output container's default audio codec
+    pub audio_only: bool,
     pub metadata: HashMap<String, String>,
     pub background_path: Option<String>,
 
@@ -19,6 +29,8 @@ pub struct VideoOptions {
     pub audio_codec: String,
     pub audio_codec_params: HashMap<String, String>,
     pub audio_channels: i32,
+    /// Titles of extra audio streams muxed after the full mix, e.g. one per APU channel
+    pub audio_stem_titles: Vec<String>,
     pub sample_format_in: String,
     pub sample_format_out: String,
     pub sample_rate: i32
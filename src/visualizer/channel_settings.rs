@@ -119,61 +119,111 @@ impl ChannelSettingsManager {
     }
 }
 
+impl ChannelSettingsManager {
+    /// Default settings for `count` consoles, four channels each
+    pub fn with_consoles(count: usize) -> Self {
+        let mut result = Self(Vec::new());
+        result.extend_consoles(count);
+        result
+    }
+
+    /// Add default settings for consoles that don't have any yet, up to `count` consoles
+    pub fn extend_consoles(&mut self, count: usize) {
+        for console in self.console_count()..count {
+            let chip = chip_name(console);
+            // Consoles past the second reuse the first two palettes with a rotated hue
+            let hue_rotation = 120.0 * (console / 2) as f32;
+            for (name, colors) in DEFAULT_PALETTES[console % 2] {
+                let colors: Vec<Color> = colors
+                    .iter()
+                    .map(|&(r, g, b)| rotate_hue(Color::from_rgba8(r, g, b, 0xFF), hue_rotation))
+                    .collect();
+                self.0.push(ChannelSettings::new(&chip, name, &colors));
+            }
+        }
+    }
+
+    fn console_count(&self) -> usize {
+        self.0.len().div_ceil(4)
+    }
+}
+
 impl Default for ChannelSettingsManager {
     fn default() -> Self {
-        Self(vec![
-            ChannelSettings::new("LR35902", "Pulse 1", &[
-                Color::from_rgba8(0xFF, 0xBF, 0xD4, 0xFF),
-                Color::from_rgba8(0xFF, 0x73, 0x8A, 0xFF),
-                Color::from_rgba8(0xFF, 0x40, 0x40, 0xFF),
-                Color::from_rgba8(0xFF, 0x73, 0x8A, 0xFF)
-            ]),
-            ChannelSettings::new("LR35902", "Pulse 2", &[
-                Color::from_rgba8(0xFF, 0xE0, 0xA0, 0xFF),
-                Color::from_rgba8(0xFF, 0xC0, 0x40, 0xFF),
-                Color::from_rgba8(0xFF, 0xFF, 0x40, 0xFF),
-                Color::from_rgba8(0xFF, 0xC0, 0x40, 0xFF)
-            ]),
-            ChannelSettings::new("LR35902", "Wave", &[
-                Color::from_rgba8(0x40, 0xFF, 0x40, 0xFF),
-                Color::from_rgba8(0x9A, 0x4F, 0xFF, 0xFF),
-                Color::from_rgba8(0x38, 0xAB, 0xF2, 0xFF),
-                Color::from_rgba8(0xAC, 0xED, 0x32, 0xFF),
-                Color::from_rgba8(0x24, 0x7B, 0xA0, 0xFF),
-                Color::from_rgba8(0x0F, 0xF4, 0xC6, 0xFF)
-            ]),
-            ChannelSettings::new("LR35902", "Noise", &[
-                Color::from_rgba8(0xC0, 0xC0, 0xC0, 0xFF),
-                Color::from_rgba8(0x80, 0xF0, 0xFF, 0xFF)
-            ]),
-            ChannelSettings::new("LR35902 (2x)", "Pulse 1", &[
-                Color::from_rgba8(0xB5, 0xE1, 0xFF, 0xFF),
-                Color::from_rgba8(0x56, 0xC8, 0xFF, 0xFF),
-                Color::from_rgba8(0x0E, 0x80, 0xC8, 0xFF),
-                Color::from_rgba8(0x56, 0xC8, 0xFF, 0xFF)
-            ]),
-            ChannelSettings::new("LR35902 (2x)", "Pulse 2", &[
-                Color::from_rgba8(0xDB, 0x95, 0xB8, 0xFF),
-                Color::from_rgba8(0xB3, 0x56, 0x84, 0xFF),
-                Color::from_rgba8(0x8A, 0x25, 0x57, 0xFF),
-                Color::from_rgba8(0xB3, 0x56, 0x84, 0xFF)
-            ]),
-            ChannelSettings::new("LR35902 (2x)", "Wave", &[
-                Color::from_rgba8(0xFF, 0x99, 0xEE, 0xFF),
-                Color::from_rgba8(0xD8, 0xE1, 0xEB, 0xFF),
-                Color::from_rgba8(0x69, 0x8D, 0xF0, 0xFF),
-                Color::from_rgba8(0xFA, 0xA2, 0x43, 0xFF),
-                Color::from_rgba8(0x1B, 0xE3, 0x93, 0xFF),
-                Color::from_rgba8(0x37, 0xCB, 0xF0, 0xFF)
-            ]),
-            ChannelSettings::new("LR35902 (2x)", "Noise", &[
-                Color::from_rgba8(0x07, 0x7D, 0x5A, 0xFF),
-                Color::from_rgba8(0x9F, 0xB8, 0xED, 0xFF)
-            ])
-        ])
+        Self::with_consoles(2)
     }
 }
 
+/// Chip name shown in the config for a console, e.g. "LR35902 (3x)" for the third one
+pub fn chip_name(console: usize) -> String {
+    match console {
+        0 => "LR35902".to_string(),
+        _ => format!("LR35902 ({}x)", console + 1)
+    }
+}
+
+/// Inverse of `chip_name`
+fn chip_console(chip: &str) -> Option<usize> {
+    match chip {
+        "LR35902" => Some(0),
+        _ => chip
+            .strip_prefix("LR35902 (")
+            .and_then(|s| s.strip_suffix("x)"))
+            .and_then(|n| n.parse::<usize>().ok())
+            .filter(|&n| n >= 2)
+            .map(|n| n - 1)
+    }
+}
+
+/// Rotate a color's hue by `degrees`, using the same matrix as SVG's feColorMatrix hueRotate
+fn rotate_hue(color: Color, degrees: f32) -> Color {
+    if degrees % 360.0 == 0.0 {
+        return color;
+    }
+
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let matrix = [
+        [0.213 + cos * 0.787 - sin * 0.213, 0.715 - cos * 0.715 - sin * 0.715, 0.072 - cos * 0.072 + sin * 0.928],
+        [0.213 - cos * 0.213 + sin * 0.143, 0.715 + cos * 0.285 + sin * 0.140, 0.072 - cos * 0.072 - sin * 0.283],
+        [0.213 - cos * 0.213 - sin * 0.787, 0.715 - cos * 0.715 + sin * 0.715, 0.072 + cos * 0.928 + sin * 0.072]
+    ];
+    let rgb = [color.red(), color.green(), color.blue()];
+    let [r, g, b] = matrix.map(|row| (row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]).clamp(0.0, 1.0));
+
+    Color::from_rgba(r, g, b, color.alpha()).unwrap()
+}
+
+type Palette = [(&'static str, &'static [(u8, u8, u8)]); 4];
+
+const DEFAULT_PALETTES: [Palette; 2] = [
+    [
+        ("Pulse 1", &[(0xFF, 0xBF, 0xD4), (0xFF, 0x73, 0x8A), (0xFF, 0x40, 0x40), (0xFF, 0x73, 0x8A)]),
+        ("Pulse 2", &[(0xFF, 0xE0, 0xA0), (0xFF, 0xC0, 0x40), (0xFF, 0xFF, 0x40), (0xFF, 0xC0, 0x40)]),
+        ("Wave", &[
+            (0x40, 0xFF, 0x40),
+            (0x9A, 0x4F, 0xFF),
+            (0x38, 0xAB, 0xF2),
+            (0xAC, 0xED, 0x32),
+            (0x24, 0x7B, 0xA0),
+            (0x0F, 0xF4, 0xC6)
+        ]),
+        ("Noise", &[(0xC0, 0xC0, 0xC0), (0x80, 0xF0, 0xFF)])
+    ],
+    [
+        ("Pulse 1", &[(0xB5, 0xE1, 0xFF), (0x56, 0xC8, 0xFF), (0x0E, 0x80, 0xC8), (0x56, 0xC8, 0xFF)]),
+        ("Pulse 2", &[(0xDB, 0x95, 0xB8), (0xB3, 0x56, 0x84), (0x8A, 0x25, 0x57), (0xB3, 0x56, 0x84)]),
+        ("Wave", &[
+            (0xFF, 0x99, 0xEE),
+            (0xD8, 0xE1, 0xEB),
+            (0x69, 0x8D, 0xF0),
+            (0xFA, 0xA2, 0x43),
+            (0x1B, 0xE3, 0x93),
+            (0x37, 0xCB, 0xF0)
+        ]),
+        ("Noise", &[(0x07, 0x7D, 0x5A), (0x9F, 0xB8, 0xED)])
+    ]
+];
+
 #[derive(Serialize, Deserialize, Default, Clone)]
 #[serde(default)]
 struct PianoRollChannelConfig {
@@ -219,6 +269,9 @@ impl<'de> Deserialize<'de> for ChannelSettingsManager {
         let settings: BTreeMap<String, BTreeMap<String, PianoRollChannelConfig>> = BTreeMap::deserialize(deserializer)?;
 
         for (chip, chip_settings) in settings {
+            if let Some(console) = chip_console(&chip) {
+                result.extend_consoles(console + 1);
+            }
             for (channel, channel_settings) in chip_settings {
                 if let Some(settings) = result.settings_mut_by_name(&chip, &channel) {
                     let mut colors = settings.colors();
@@ -2,16 +2,18 @@ mod filters;
 pub mod channel_settings;
 mod oscilloscope;
 mod piano_roll;
+mod screen;
 mod tile_map;
 
-use tiny_skia::{Color, Pixmap, Rect};
+use tiny_skia::{Color, Paint, Pixmap, Rect, Transform};
 use channel_settings::{ChannelSettingsManager, ChannelSettings};
 use filters::HighPassIIR;
 use oscilloscope::OscilloscopeState;
 use piano_roll::PianoRollState;
+use screen::ScreenState;
 use sameboy::{ApuChannel, ApuStateReceiver};
 use tile_map::TileMap;
-use crate::config::PianoRollConfig;
+use crate::config::{PianoRollConfig, ScreenConfig};
 
 pub const APU_STATE_BUF_SIZE: usize = 4096;
 const FONT_IMAGE: &'static [u8] = include_bytes!("8x8_font.png");
@@ -31,6 +33,7 @@ pub struct Visualizer {
     channels: usize,
     canvas: Pixmap,
     config: PianoRollConfig,
+    screen_config: ScreenConfig,
 
     channel_last_states: Vec<ChannelState>,
     channel_filters: Vec<HighPassIIR>,
@@ -38,28 +41,34 @@ pub struct Visualizer {
     piano_roll_states: Vec<PianoRollState>,
 
     font: TileMap,
-    oscilloscope_divider_cache: Option<(f32, Pixmap)>
+    oscilloscope_divider_cache: Option<(f32, Pixmap)>,
+    screens: Vec<ScreenState>,
+    brightness: f32
 }
 
 impl Visualizer {
-    pub fn new(channels: usize, width: u32, height: u32, sample_rate: u32, config: PianoRollConfig) -> Self {
+    pub fn new(channels: usize, width: u32, height: u32, sample_rate: u32, frame_rate: f64, mut config: PianoRollConfig, screen_config: ScreenConfig) -> Self {
+        config.settings.extend_consoles(channels.div_ceil(4));
         let mut oscilloscope_states: Vec<OscilloscopeState> = Vec::with_capacity(channels);
         let mut piano_roll_states: Vec<PianoRollState> = Vec::with_capacity(channels);
         for _ in 0..channels {
             oscilloscope_states.push(OscilloscopeState::new());
-            piano_roll_states.push(PianoRollState::new(sample_rate as f32, config.speed_multiplier as f32 * 4.0, config.starting_octave as f32));
+            piano_roll_states.push(PianoRollState::new(sample_rate as f32, frame_rate as f32, config.speed_multiplier as f32 * 4.0, config.starting_octave as f32));
         }
 
         Self {
             channels,
             canvas: Pixmap::new(width, height).unwrap(),
             config,
+            screen_config,
             channel_last_states: vec![ChannelState::default(); channels],
             channel_filters: vec![HighPassIIR::new(sample_rate as f32, 300.0); channels],
             oscilloscope_states,
             piano_roll_states,
             font: TileMap::new(Pixmap::decode_png(FONT_IMAGE).unwrap(), 8, 8, FONT_CHAR_MAP),
-            oscilloscope_divider_cache: None
+            oscilloscope_divider_cache: None,
+            screens: Vec::new(),
+            brightness: 1.0
         }
     }
 
@@ -95,7 +104,33 @@ impl Visualizer {
             self.canvas.height() as f32 - oscilloscopes_pos.height()
         ).unwrap();
 
+        let (piano_roll_pos, screen_pos) = self.split_screen_panel(piano_roll_pos);
         self.draw_piano_roll(piano_roll_pos);
+        if let Some(screen_pos) = screen_pos {
+            self.draw_screens(screen_pos);
+        }
+
+        if self.brightness < 1.0 {
+            self.draw_fade();
+        }
+    }
+
+    /// Fade the whole frame (including the video background behind it) towards black, 1.0 is unchanged
+    pub fn set_brightness(&mut self, brightness: f32) {
+        self.brightness = brightness.clamp(0.0, 1.0);
+    }
+
+    fn draw_fade(&mut self) {
+        let mut paint = Paint::default();
+        paint.anti_alias = false;
+        paint.set_color(Color::from_rgba(0.0, 0.0, 0.0, 1.0 - self.brightness).unwrap());
+
+        self.canvas.fill_rect(
+            Rect::from_xywh(0.0, 0.0, self.canvas.width() as f32, self.canvas.height() as f32).unwrap(),
+            &paint,
+            Transform::identity(),
+            None
+        );
     }
 
     pub fn settings_manager(&self) -> &ChannelSettingsManager {
@@ -109,6 +144,13 @@ impl Visualizer {
     pub fn is_vertical_layout(&self) -> bool {
         self.canvas.height() > self.canvas.width()
     }
+
+    pub fn max_channel_volume(&self) -> f32 {
+        self.channel_last_states
+            .iter()
+            .map(|s| s.volume)
+            .fold(0.0, f32::max)
+    }
 }
 
 impl ApuStateReceiver for Visualizer {
@@ -278,18 +278,27 @@ impl Visualizer {
             .filter(|&i| !self.config.settings.settings(i).unwrap().hidden())
             .collect();
 
-        for row in channel_indices.chunks(max_channels_per_row) {
-            let channel_width = pos.width() / row.len() as f32;
+        // Rows split the available height evenly, top to bottom
+        let row_count = channel_indices.len().div_ceil(max_channels_per_row).max(1);
+        let row_height = (pos.height() / row_count as f32).floor();
+        for (row_index, row) in channel_indices.chunks(max_channels_per_row).enumerate() {
+            let row_pos = Rect::from_xywh(
+                pos.x(),
+                pos.y() + row_height * row_index as f32,
+                pos.width(),
+                row_height
+            ).unwrap();
+            let channel_width = row_pos.width() / row.len() as f32;
             for (i, &channel) in row.iter().enumerate() {
                 let channel_pos = Rect::from_xywh(
-                    pos.x() + (channel_width * i as f32),
-                    pos.y(),
+                    row_pos.x() + (channel_width * i as f32),
+                    row_pos.y(),
                     channel_width,
-                    pos.height()
+                    row_pos.height()
                 ).unwrap();
                 self.draw_oscilloscope_view(channel, channel_pos);
             }
-            self.draw_oscilloscope_dividers(pos, channel_width);
+            self.draw_oscilloscope_dividers(row_pos, channel_width);
         }
     }
 }
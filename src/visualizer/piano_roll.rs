@@ -52,10 +52,10 @@ pub struct PianoRollState {
 }
 
 impl PianoRollState {
-    pub fn new(sample_rate: f32, scroll_speed: f32, starting_octave: f32) -> Self {
+    pub fn new(sample_rate: f32, frame_rate: f32, scroll_speed: f32, starting_octave: f32) -> Self {
         Self {
             slices: HeapRb::new(APU_STATE_BUF_SIZE),
-            samples_per_frame: sample_rate / (60.0 * scroll_speed),
+            samples_per_frame: sample_rate / (frame_rate * scroll_speed),
             taken_samples: 0.0,
             starting_octave
         }
@@ -0,0 +1,129 @@
+use tiny_skia::{FilterQuality, IntSize, Paint, Pixmap, PixmapPaint, Rect, Transform};
+use crate::config::ScreenSide;
+use super::Visualizer;
+
+const SGB_SCREEN_SIZE: (usize, usize) = (256, 224);
+// Position of the Game Boy's screen inside the SGB border
+const SGB_SCREEN_OFFSET: (usize, usize) = (48, 40);
+const LCD_SIZE: (usize, usize) = (160, 144);
+
+pub struct ScreenState {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>
+}
+
+impl Visualizer {
+    /// Replace the LCD contents shown in the screen panel, one 32-bit RGBA buffer per console
+    pub fn set_screens(&mut self, screens: &[(usize, usize, Vec<u32>)]) {
+        let crop_border = !self.screen_config.sgb_border;
+        self.screens = screens
+            .iter()
+            .map(|(width, height, buffer)| match (crop_border, (*width, *height)) {
+                (true, SGB_SCREEN_SIZE) => {
+                    let (x, y) = SGB_SCREEN_OFFSET;
+                    let pixels: Vec<u32> = buffer
+                        .chunks_exact(*width)
+                        .skip(y)
+                        .take(LCD_SIZE.1)
+                        .flat_map(|row| row[x..x + LCD_SIZE.0].iter().copied())
+                        .collect();
+                    (LCD_SIZE.0, LCD_SIZE.1, pixels)
+                },
+                _ => (*width, *height, buffer.clone())
+            })
+            .map(|(width, height, buffer)| ScreenState {
+                width: width as u32,
+                height: height as u32,
+                // The buffer is already in RGBA byte order, and always opaque
+                pixels: buffer.iter().flat_map(|p| p.to_ne_bytes()).collect()
+            })
+            .collect();
+    }
+
+    /// Split the screen panel off the piano roll area, returning (piano roll, panel)
+    pub fn split_screen_panel(&self, pos: Rect) -> (Rect, Option<Rect>) {
+        if !self.screen_config.enabled {
+            return (pos, None);
+        }
+
+        let size = self.screen_config.size.clamp(0.0, 1.0);
+        let (piano_roll_pos, panel_pos) = match (self.is_vertical_layout(), self.screen_config.side) {
+            (false, side) => {
+                let panel_width = (pos.width() * size).round();
+                let piano_roll_width = pos.width() - panel_width;
+                match side {
+                    ScreenSide::Left => (
+                        Rect::from_xywh(pos.x() + panel_width, pos.y(), piano_roll_width, pos.height()),
+                        Rect::from_xywh(pos.x(), pos.y(), panel_width, pos.height())
+                    ),
+                    ScreenSide::Right => (
+                        Rect::from_xywh(pos.x(), pos.y(), piano_roll_width, pos.height()),
+                        Rect::from_xywh(pos.x() + piano_roll_width, pos.y(), panel_width, pos.height())
+                    )
+                }
+            },
+            (true, side) => {
+                let panel_height = (pos.height() * size).round();
+                let piano_roll_height = pos.height() - panel_height;
+                match side {
+                    ScreenSide::Left => (
+                        Rect::from_xywh(pos.x(), pos.y() + panel_height, pos.width(), piano_roll_height),
+                        Rect::from_xywh(pos.x(), pos.y(), pos.width(), panel_height)
+                    ),
+                    ScreenSide::Right => (
+                        Rect::from_xywh(pos.x(), pos.y(), pos.width(), piano_roll_height),
+                        Rect::from_xywh(pos.x(), pos.y() + piano_roll_height, pos.width(), panel_height)
+                    )
+                }
+            }
+        };
+
+        match (piano_roll_pos, panel_pos) {
+            (Some(piano_roll_pos), panel_pos) => (piano_roll_pos, panel_pos),
+            (None, _) => (pos, None)
+        }
+    }
+
+    /// Draw every console's screen side by side, centered at the largest integer scale that fits
+    pub fn draw_screens(&mut self, pos: Rect) {
+        let mut bg_paint = Paint::default();
+        bg_paint.anti_alias = false;
+        bg_paint.set_color(self.screen_config.background_color);
+        self.canvas.fill_rect(pos, &bg_paint, Transform::identity(), None);
+
+        let total_width: u32 = self.screens.iter().map(|s| s.width).sum();
+        let max_height = self.screens.iter().map(|s| s.height).max().unwrap_or_default();
+        if total_width == 0 || max_height == 0 {
+            return;
+        }
+
+        // Integer scales keep the pixels sharp, panels smaller than the LCD shrink it to fit instead
+        let fit = (pos.width() / total_width as f32).min(pos.height() / max_height as f32);
+        let scale = if fit >= 1.0 { fit.floor() } else { fit };
+        if total_width as f32 * scale < 1.0 || max_height as f32 * scale < 1.0 {
+            return;
+        }
+        let mut x = pos.x() + ((pos.width() - total_width as f32 * scale) / 2.0).floor();
+
+        let paint = PixmapPaint {
+            quality: FilterQuality::Nearest,
+            ..PixmapPaint::default()
+        };
+        for screen in self.screens.iter() {
+            let size = IntSize::from_wh(screen.width, screen.height).unwrap();
+            if let Some(pixmap) = Pixmap::from_vec(screen.pixels.clone(), size) {
+                let y = pos.y() + ((pos.height() - screen.height as f32 * scale) / 2.0).floor();
+                self.canvas.draw_pixmap(
+                    0,
+                    0,
+                    pixmap.as_ref(),
+                    &paint,
+                    Transform::from_scale(scale, scale).post_translate(x, y),
+                    None
+                );
+            }
+            x += screen.width as f32 * scale;
+        }
+    }
+}